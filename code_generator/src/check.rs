@@ -1,21 +1,25 @@
-use syn::Result;
+use std::collections::HashSet;
+
+use syn::{visit::Visit, Pat, Result};
 
 use crate::{
-    html::{AttrExprType, Attribute, Classic, Element, ExprElement},
+    html::{AttrExprType, Attribute, Case, Classic, Condition, Element, ExprElement, Settled},
     state_block::LocalDetails,
+    visitor::BindingExtractor,
 };
 
 fn check_ident_expr_attrs(
     _element: &Element,
     attrs: &Vec<Attribute>,
     details: &LocalDetails,
+    bindings: &HashSet<String>,
 ) -> Result<()> {
     for attr in attrs {
         match attr.expr {
             AttrExprType::Ident(ref ident) => {
                 if attr.namespace == "on" {
                     details.events_contains_ident(ident)?;
-                } else {
+                } else if !bindings.contains(&ident.to_string()) {
                     details.states_contains_ident(ident)?;
                 }
             }
@@ -25,7 +29,24 @@ fn check_ident_expr_attrs(
     Ok(())
 }
 
+fn with_pat_bindings(bindings: &HashSet<String>, pat: &Pat) -> HashSet<String> {
+    let mut extractor = BindingExtractor::new();
+    extractor.visit_pat(pat);
+    let mut bindings = bindings.clone();
+    bindings.extend(extractor.idents.iter().map(|ident| ident.to_string()));
+    bindings
+}
+
 pub fn check_ident_expr(elements: &Vec<Element>, details: &LocalDetails) -> Result<()> {
+    check_ident_expr_with_bindings(elements, details, &HashSet::new())
+}
+
+fn check_ident_expr_with_bindings(
+    elements: &Vec<Element>,
+    details: &LocalDetails,
+    bindings: &HashSet<String>,
+) -> Result<()> {
+    //! `bindings` are the idents introduced by a parent block (ex: `{case Some(value)}`).
     for el in elements {
         match el {
             Element::Classic(Classic {
@@ -33,11 +54,36 @@ pub fn check_ident_expr(elements: &Vec<Element>, details: &LocalDetails) -> Resu
                 ref children,
                 ..
             }) => {
-                check_ident_expr_attrs(&el, attrs, details)?;
-                check_ident_expr(children, details)?;
+                check_ident_expr_attrs(&el, attrs, details, bindings)?;
+                check_ident_expr_with_bindings(children, details, bindings)?;
             }
             Element::ExprElement(el_expr) => match el_expr {
-                ExprElement::Ident(ref ident) => details.states_contains_ident(ident)?,
+                ExprElement::Ident(ref ident) if !bindings.contains(&ident.to_string()) => {
+                    details.states_contains_ident(ident)?
+                }
+                ExprElement::If { conditions } => {
+                    for Condition { children, .. } in conditions {
+                        check_ident_expr_with_bindings(children, details, bindings)?;
+                    }
+                }
+                ExprElement::Match { cases, .. } => {
+                    for Case { pat, children, .. } in cases {
+                        let bindings = with_pat_bindings(bindings, pat);
+                        check_ident_expr_with_bindings(children, details, &bindings)?;
+                    }
+                }
+                ExprElement::Await {
+                    pending,
+                    then,
+                    catch,
+                    ..
+                } => {
+                    check_ident_expr_with_bindings(pending, details, bindings)?;
+                    for Settled { pat, children } in then.iter().chain(catch) {
+                        let bindings = with_pat_bindings(bindings, pat);
+                        check_ident_expr_with_bindings(children, details, &bindings)?;
+                    }
+                }
                 _ => (),
             },
             _ => {}
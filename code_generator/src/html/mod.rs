@@ -1,21 +1,69 @@
-use proc_macro2::{Delimiter, Span, TokenTree};
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
 use proc_macro2_diagnostics::SpanDiagnosticExt;
+use quote::quote_spanned;
 use syn::{
     braced, custom_punctuation,
     ext::IdentExt,
     parse::{discouraged::Speculative, Parse, ParseBuffer, ParseStream},
     token::{Colon, Eq},
-    Block, Error, Expr, ExprForLoop, Ident, LitStr, Result, Token,
+    Block, Error, Expr, ExprForLoop, Ident, LitStr, Pat, PatWild, Result, Token,
 };
 
+mod raw;
 mod utils;
 
+pub use raw::Template;
+
 custom_punctuation!(OpenTag, <);
 custom_punctuation!(CloseTag, >);
 custom_punctuation!(OpenClosingTag, </);
 custom_punctuation!(SelfCloseVoidTag, />);
 
 const INNER_ERROR: &str = "Inner element can't be parsed.";
+/// Slots don't exist yet, the children of a component would be dropped.
+const COMPONENT_CHILDREN_ERROR: &str = "A component can't have children (ex: `<Card/>`).";
+/// Elements without children nor closing tag (ex: `<br>` or `<input type="range">`).
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+pub fn replace_store_sigils(tokens: TokenStream) -> TokenStream {
+    //! `$count` in a block of the markup reads an auto-subscribed store,
+    //! it's replaced by `store!(count)` to be parsed as rust code.
+    replace_sigils(tokens, false)
+}
+
+pub fn replace_code_store_sigils(tokens: TokenStream) -> TokenStream {
+    //! `$count` in the state's block : every sigil reads a store, not only the ones in braces.
+    replace_sigils(tokens, true)
+}
+
+fn replace_sigils(tokens: TokenStream, in_brace: bool) -> TokenStream {
+    let mut trees = tokens.into_iter().peekable();
+    let mut replaced = TokenStream::new();
+    while let Some(tree) = trees.next() {
+        match tree {
+            TokenTree::Group(group) => {
+                let in_brace = in_brace || group.delimiter() == Delimiter::Brace;
+                let mut new_group =
+                    Group::new(group.delimiter(), replace_sigils(group.stream(), in_brace));
+                new_group.set_span(group.span());
+                replaced.extend([TokenTree::Group(new_group)]);
+            }
+            TokenTree::Punct(punct)
+                if in_brace
+                    && punct.as_char() == '$'
+                    && matches!(trees.peek(), Some(TokenTree::Ident(_))) =>
+            {
+                let ident = trees.next();
+                replaced.extend(quote_spanned!(punct.span()=> store!(#ident)));
+            }
+            tree => replaced.extend([tree]),
+        }
+    }
+    replaced
+}
 
 // pub struct Node {
 //     el: Element,
@@ -164,8 +212,17 @@ impl Parse for Attribute {
     }
 }
 
+mod kw {
+    syn::custom_keyword!(key);
+    syn::custom_keyword!(case);
+    syn::custom_keyword!(then);
+    syn::custom_keyword!(catch);
+    syn::custom_keyword!(template);
+}
+
 struct ForLoopWithoutBlock {
     expr: ExprForLoop,
+    key: Option<Expr>,
 }
 
 impl Parse for ForLoopWithoutBlock {
@@ -178,6 +235,16 @@ impl Parse for ForLoopWithoutBlock {
         let in_token: Token![in] = input.parse()?;
         let expr: Expr = input.call(Expr::parse_without_eager_brace)?;
 
+        // {for item in items; key = item.id}
+        let key = if input.peek(Token![;]) {
+            input.parse::<Token![;]>()?;
+            input.parse::<kw::key>()?;
+            input.parse::<Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
         let for_loop = ExprForLoop {
             attrs,
             label,
@@ -191,7 +258,10 @@ impl Parse for ForLoopWithoutBlock {
         };
         println!("Loop created");
         println!("{:#?}", for_loop);
-        Ok(ForLoopWithoutBlock { expr: for_loop })
+        Ok(ForLoopWithoutBlock {
+            expr: for_loop,
+            key,
+        })
     }
 }
 
@@ -201,15 +271,40 @@ pub struct Condition {
     pub children: Vec<Element>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct Case {
+    pub pat: Pat,
+    pub guard: Option<Expr>,
+    pub children: Vec<Element>,
+}
+
+/// `{then value}` or `{catch err}` part of an await block.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Settled {
+    pub pat: Pat,
+    pub children: Vec<Element>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ExprElement {
     For {
         expr: ExprForLoop,
+        key: Option<Expr>,
         children: Vec<Element>,
     },
     If {
         conditions: Vec<Condition>,
     },
+    Match {
+        expr: Expr,
+        cases: Vec<Case>,
+    },
+    Await {
+        future: Expr,
+        pending: Vec<Element>,
+        then: Option<Settled>,
+        catch: Option<Settled>,
+    },
     Block(Block),
     Ident(Ident),
     Lit(LitStr),
@@ -230,6 +325,35 @@ fn parse_brace(input: ParseStream) -> Result<ParseBuffer> {
 
 // fn handle_inner_children(input: ParseStream) -> Vec<Element> {
 // }
+fn is_component(name: &str) -> bool {
+    //! `<Counter/>` is a child component, its tag is uppercase.
+    name.starts_with(char::is_uppercase)
+}
+
+fn is_void_element(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name.to_lowercase().as_str())
+}
+
+fn void_closing_error(span: Span, name: &str) -> Error {
+    Error::new(
+        span,
+        format!("`{name}` is a void element, it can't be closed (remove `</{name}>`)."),
+    )
+}
+
+fn check_not_void_closing(input: ParseStream) -> Result<()> {
+    //! Raise a clear error on a closing tag of a void element (ex: `</input>`).
+    let fork = input.fork();
+    if fork.parse::<OpenClosingTag>().is_ok() {
+        if let Ok(name) = fork.parse::<Ident>() {
+            if is_void_element(&name.to_string()) {
+                Err(void_closing_error(name.span(), &name.to_string()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
 fn check_tag_name(name: &Ident, close_name: Ident) -> Result<()> {
     if close_name.to_string() != name.to_string() {
         let mut error = Error::new(
@@ -295,6 +419,150 @@ fn parse_expr_without_eager_brace(inner: ParseStream) -> Result<Expr> {
     })
 }
 
+fn parse_match(input: ParseStream) -> Result<ExprElement> {
+    let inner = parse_brace(input)?;
+    let match_token: Token![match] = inner.parse()?;
+    let expr = parse_expr_without_eager_brace(&inner)?;
+    let mut cases: Vec<Case> = Vec::new();
+
+    // Collect cases and their children Elements
+    loop {
+        match parse_brace(&input.fork()) {
+            Ok(fork) if fork.peek(Token![/]) => {
+                let inner = parse_brace(input)?;
+                inner.parse::<Token![/]>()?;
+                if let Err(last) = inner.parse::<Token![match]>() {
+                    double_error(
+                        &match_token.span,
+                        "Closing element's name does not match an opening element's name.",
+                        &last.span(),
+                        "No closing element's name match this elements name.",
+                    )?;
+                };
+                break;
+            }
+            Ok(fork) if fork.peek(kw::case) => {
+                let inner = parse_brace(input)?;
+                inner.parse::<kw::case>()?;
+                let pat = utils::multi_pat_with_leading_vert(&inner)?;
+                let guard = if inner.peek(Token![if]) {
+                    inner.parse::<Token![if]>()?;
+                    Some(parse_expr_without_eager_brace(&inner)?)
+                } else {
+                    None
+                };
+                cases.push(Case {
+                    pat,
+                    guard,
+                    children: Vec::new(),
+                });
+            }
+            _ => {
+                let child = input.parse()?;
+                match cases.last_mut() {
+                    Some(case) => case.children.push(child),
+                    None => Err(Error::new(
+                        match_token.span,
+                        "Match's children must follow a case ({case Pattern}).",
+                    ))?,
+                }
+            }
+        }
+    }
+    Ok(ExprElement::Match { expr, cases })
+}
+
+fn parse_settled_pat(inner: ParseStream) -> Result<Pat> {
+    //! `{then}` without pattern ignores the value.
+    if inner.is_empty() {
+        Ok(Pat::Wild(PatWild {
+            attrs: Vec::new(),
+            underscore_token: Default::default(),
+        }))
+    } else {
+        utils::multi_pat_with_leading_vert(inner)
+    }
+}
+
+fn parse_await(input: ParseStream) -> Result<ExprElement> {
+    let inner = parse_brace(input)?;
+    let await_token: Token![await] = inner.parse()?;
+    let future = inner.parse().map_err(|_| {
+        Error::new(
+            inner.span(),
+            "An expression (the future) is required for `await`.",
+        )
+    })?;
+    let mut pending = Vec::new();
+    let mut then: Option<Settled> = None;
+    let mut catch: Option<Settled> = None;
+
+    // Children go to the last opened part : pending, then or catch
+    loop {
+        match parse_brace(&input.fork()) {
+            Ok(fork) if fork.peek(Token![/]) => {
+                let inner = parse_brace(input)?;
+                inner.parse::<Token![/]>()?;
+                if let Err(last) = inner.parse::<Token![await]>() {
+                    double_error(
+                        &await_token.span,
+                        "Closing element's name does not match an opening element's name.",
+                        &last.span(),
+                        "No closing element's name match this elements name.",
+                    )?;
+                };
+                break;
+            }
+            Ok(fork) if fork.peek(kw::then) => {
+                let inner = parse_brace(input)?;
+                let token = inner.parse::<kw::then>()?;
+                if then.is_some() || catch.is_some() {
+                    double_error(
+                        &await_token.span,
+                        "An await block has at most one then, before its catch.",
+                        &token.span,
+                        "Unexpected then.",
+                    )?;
+                }
+                then = Some(Settled {
+                    pat: parse_settled_pat(&inner)?,
+                    children: Vec::new(),
+                });
+            }
+            Ok(fork) if fork.peek(kw::catch) => {
+                let inner = parse_brace(input)?;
+                let token = inner.parse::<kw::catch>()?;
+                if catch.is_some() {
+                    double_error(
+                        &await_token.span,
+                        "An await block has at most one catch.",
+                        &token.span,
+                        "Unexpected catch.",
+                    )?;
+                }
+                catch = Some(Settled {
+                    pat: parse_settled_pat(&inner)?,
+                    children: Vec::new(),
+                });
+            }
+            _ => {
+                let child = input.parse()?;
+                match (catch.as_mut(), then.as_mut()) {
+                    (Some(catch), _) => catch.children.push(child),
+                    (None, Some(then)) => then.children.push(child),
+                    (None, None) => pending.push(child),
+                }
+            }
+        }
+    }
+    Ok(ExprElement::Await {
+        future,
+        pending,
+        then,
+        catch,
+    })
+}
+
 impl Parse for ExprElement {
     fn parse(input: ParseStream) -> Result<Self> {
         println!("START parse EXPR");
@@ -305,7 +573,7 @@ impl Parse for ExprElement {
             let inner = &parse_brace(input)?;
             let mut children: Vec<Element> = Vec::new();
             let open_token: Token![for] = inner.fork().parse()?;
-            let expr = inner.parse::<ForLoopWithoutBlock>()?.expr;
+            let ForLoopWithoutBlock { expr, key } = inner.parse()?;
             println!("Children -> 'for'");
             // Collect children Elements
             loop {
@@ -332,7 +600,15 @@ impl Parse for ExprElement {
                     Err(_) => children.push(input.parse()?),
                 }
             }
-            Ok(ExprElement::For { expr, children })
+            Ok(ExprElement::For {
+                expr,
+                key,
+                children,
+            })
+        } else if fork_inner.peek(Token![match]) {
+            parse_match(input)
+        } else if fork_inner.peek(Token![await]) {
+            parse_await(input)
         } else if fork_inner.peek(Token![if]) {
             let mut ctx = IfChildrenCtx::new(fork_inner)?;
             println!("CTX initialized");
@@ -459,14 +735,7 @@ impl Parse for ExprElement {
                 Ok(ident) => Ok(ExprElement::Ident(ident)),
                 Err(_) => match lit_in_brace(input) {
                     Ok(lit) => Ok(ExprElement::Lit(lit)),
-                    Err(_) => {
-                        input
-                            .span()
-                            .warning("Block Element are in early stages. Don't hesitate to make proposals.")
-                            .emit_as_expr_tokens();
-
-                        Ok(ExprElement::Block(input.parse()?))
-                    }
+                    Err(_) => Ok(ExprElement::Block(input.parse()?)),
                 },
             }
         }
@@ -573,9 +842,11 @@ impl Parse for Classic {
 
         let mut children = Vec::new();
         if input.peek(SelfCloseVoidTag) {
-            // TODO: handle Void element without self closing
             // Self Close Void element
             input.parse::<SelfCloseVoidTag>()?;
+        } else if is_void_element(&name.to_string()) {
+            // Void element without self closing
+            input.parse::<CloseTag>()?;
         } else if input.peek(CloseTag) {
             input.parse::<CloseTag>()?;
 
@@ -584,6 +855,7 @@ impl Parse for Classic {
             while !input.peek(OpenClosingTag) {
                 children.push(input.parse()?)
             }
+            check_not_void_closing(input)?;
 
             // close element
             println!("\nClose tag -> '{}'", name.to_string());
@@ -604,6 +876,9 @@ impl Parse for Classic {
             println!("Close tag ended -> '{}'", name.to_string());
             let _: CloseTag = input.parse()?;
         }
+        if is_component(&name.to_string()) && !children.is_empty() {
+            Err(Error::new(name.span(), COMPONENT_CHILDREN_ERROR))?;
+        }
 
         Ok(Self {
             name: name.to_string(),
@@ -628,6 +903,7 @@ impl Parse for Element {
             if input.peek2(Token![!]) {
                 Self::Comment(parse_string_until_string(input, "-->")?)
             } else if input.peek2(Token![/]) {
+                check_not_void_closing(input)?;
                 Err(Error::new(input.span(), INNER_ERROR))?
             } else {
                 println!("Parse Classic");
@@ -655,3 +931,106 @@ impl Parse for Root {
         Ok(Root(elements))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{replace_store_sigils, Classic, Element, ExprElement};
+    use quote::quote;
+
+    #[test]
+    fn element_for_keyed() -> syn::Result<()> {
+        let el: Element =
+            syn::parse_str("{for item in items; key = item.id}<li>{item}</li>{/for}")?;
+        match el {
+            Element::ExprElement(ExprElement::For {
+                expr,
+                key: Some(key),
+                children,
+            }) => {
+                let iterated = &expr.expr;
+                assert_eq!(quote!(#iterated).to_string(), "items");
+                assert_eq!(quote!(#key).to_string(), "item . id");
+                assert_eq!(children.len(), 1);
+            }
+            _ => panic!("Element doesn't match."),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn element_match() -> syn::Result<()> {
+        let el: Element = syn::parse_str(
+            "{match status}{case Status::Loaded(data) if data.len() > 0}<p>{data}</p>{case _}<p>empty</p>{/match}",
+        )?;
+        match el {
+            Element::ExprElement(ExprElement::Match { expr, cases }) => {
+                assert_eq!(quote!(#expr).to_string(), "status");
+                assert_eq!(cases.len(), 2);
+                let (pat, guard) = (&cases[0].pat, &cases[0].guard);
+                assert_eq!(quote!(#pat).to_string(), "Status :: Loaded (data)");
+                assert_eq!(quote!(#guard).to_string(), "data . len () > 0");
+                assert!(cases[1].guard.is_none());
+                assert_eq!(cases[1].children.len(), 1);
+            }
+            _ => panic!("Element doesn't match."),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn element_await() -> syn::Result<()> {
+        let el: Element = syn::parse_str(
+            "{await load(id)}<p>loading</p>{then user}<p>{user}</p>{catch err}<p>{err}</p>{/await}",
+        )?;
+        match el {
+            Element::ExprElement(ExprElement::Await {
+                future,
+                pending,
+                then: Some(then),
+                catch: Some(catch),
+            }) => {
+                let (then_pat, catch_pat) = (&then.pat, &catch.pat);
+                assert_eq!(quote!(#future).to_string(), "load (id)");
+                assert_eq!(pending.len(), 1);
+                assert_eq!(quote!(#then_pat).to_string(), "user");
+                assert_eq!(quote!(#catch_pat).to_string(), "err");
+                assert_eq!(then.children.len(), 1);
+            }
+            _ => panic!("Element doesn't match."),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn element_void_without_self_closing() -> syn::Result<()> {
+        let el: Element = syn::parse_str(r#"<p><input type="range"><br>text</p>"#)?;
+        match el {
+            Element::Classic(Classic { name, children, .. }) => {
+                assert_eq!(name, "p");
+                assert_eq!(children.len(), 3);
+            }
+            _ => panic!("Element doesn't match."),
+        }
+        let err = syn::parse_str::<Element>("<p><input></input></p>").unwrap_err();
+        assert!(err.to_string().contains("void element"));
+        Ok(())
+    }
+
+    #[test]
+    fn component_without_children() -> syn::Result<()> {
+        let el: Element = syn::parse_str("<div><Card title={title}></Card></div>")?;
+        assert!(matches!(el, Element::Classic(Classic { children, .. }) if children.len() == 1));
+        let err = syn::parse_str::<Element>("<Card><p>hi</p></Card>").unwrap_err();
+        assert!(err.to_string().contains("can't have children"));
+        Ok(())
+    }
+
+    #[test]
+    fn store_sigils_in_blocks() {
+        let replaced = replace_store_sigils(quote!(<p>$ count {$count + 1}</p>));
+        assert_eq!(
+            replaced.to_string(),
+            quote!(<p>$ count {store!(count) + 1}</p>).to_string()
+        );
+    }
+}
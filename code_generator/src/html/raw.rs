@@ -0,0 +1,708 @@
+//! Raw-string templates (ex: `full!({ ... } r#"<p>Don't panic</p>"#)`).
+//!
+//! The markup is tokenized as HTML, so text keeps its spaces and can contain anything
+//! (quotes, `<` or emoji). Only the content of `{...}` is lexed as Rust, its tokens are
+//! spanned inside the literal when the compiler allows it (the whole literal otherwise).
+
+use std::{iter::Peekable, str::FromStr, vec::IntoIter};
+
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
+use syn::{
+    ext::IdentExt,
+    parse::{Parse, ParseStream, Parser},
+    Block, Error, Expr, Ident, LitStr, Pat, Result, Token,
+};
+
+use super::{
+    double_error, is_component, is_void_element, kw, parse_expr_without_eager_brace,
+    parse_settled_pat, utils, void_closing_error, AttrExprType, Attribute, Case, Classic,
+    Condition, Element, ExprElement, ForLoopWithoutBlock, Settled, COMPONENT_CHILDREN_ERROR,
+    INNER_ERROR,
+};
+
+/// Elements of a raw-string template : `r#"..."#` or `template = r#"..."#`.
+pub struct Template(pub Vec<Element>);
+
+impl Parse for Template {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(kw::template) {
+            input.parse::<kw::template>()?;
+            input.parse::<Token![=]>()?;
+        }
+        let lit: LitStr = input.parse()?;
+        let tokens = Tokenizer::new(&lit).tokenize()?;
+        Ok(Template(TreeBuilder::new(tokens).root()?))
+    }
+}
+
+enum Token {
+    Text(String),
+    Comment(String),
+    Open {
+        name: String,
+        attrs: Vec<Attribute>,
+        self_closing: bool,
+    },
+    Close(String),
+    Brace(TokenStream),
+}
+
+struct Tokenizer<'a> {
+    lit: &'a LitStr,
+    source: String,
+    position: usize,
+    /// Offset of the value in the literal's source, None if it can't be mapped (escapes).
+    offset: Option<usize>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(lit: &'a LitStr) -> Self {
+        let source = lit.value();
+        let repr = lit.token().to_string();
+        let offset = if repr.starts_with('r') {
+            repr.find('"').map(|quote| quote + 1)
+        } else if repr.len() == source.len() + 2 {
+            Some(1)
+        } else {
+            None
+        };
+        Self {
+            lit,
+            source,
+            position: 0,
+            offset,
+        }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        self.offset
+            .and_then(|offset| self.lit.token().subspan(start + offset..end + offset))
+            .unwrap_or_else(|| self.lit.span())
+    }
+
+    fn error(&self, start: usize, message: &str) -> Error {
+        Error::new(self.span(start, self.position), message)
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.position..]
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let length = self
+            .rest()
+            .find(|c: char| !predicate(c))
+            .unwrap_or(self.rest().len());
+        let taken = self.rest()[..length].to_string();
+        self.position += length;
+        taken
+    }
+
+    fn skip_whitespaces(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn starts_tag(&self) -> bool {
+        //! `<` only opens a tag when followed by a name, `/` or `!` (ex: `a < b` is text).
+        let mut chars = self.rest().chars();
+        chars.next() == Some('<')
+            && matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '/' || c == '!')
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, Span)>> {
+        let mut tokens = Vec::new();
+        while self.position < self.source.len() {
+            let start = self.position;
+            let token = if self.rest().starts_with("<!--") {
+                self.comment()?
+            } else if self.rest().starts_with("</") {
+                self.position += 2;
+                let name = self.take_while(|c| c != '>');
+                if !self.rest().starts_with('>') {
+                    Err(self.error(start, "Closing tag must end with `>`."))?
+                }
+                self.position += 1;
+                Token::Close(name.trim().to_string())
+            } else if self.starts_tag() {
+                self.open_tag()?
+            } else if self.rest().starts_with('{') {
+                Token::Brace(self.brace()?)
+            } else {
+                let mut text = String::new();
+                while self.position < self.source.len()
+                    && !self.starts_tag()
+                    && !self.rest().starts_with('{')
+                {
+                    let c = self.rest().chars().next().unwrap();
+                    text.push(c);
+                    self.position += c.len_utf8();
+                }
+                // Whitespaces with a new line are indentation of the template
+                if text.trim().is_empty() && text.contains('\n') {
+                    continue;
+                }
+                Token::Text(text)
+            };
+            tokens.push((token, self.span(start, self.position)));
+        }
+        Ok(tokens)
+    }
+
+    fn comment(&mut self) -> Result<Token> {
+        let start = self.position;
+        match self.rest()[4..].find("-->") {
+            Some(length) => {
+                let comment = self.rest()[4..4 + length].to_string();
+                self.position += length + 7;
+                Ok(Token::Comment(comment))
+            }
+            None => {
+                self.position = self.source.len();
+                Err(self.error(start, "Comment must be closed (`-->`)."))
+            }
+        }
+    }
+
+    fn open_tag(&mut self) -> Result<Token> {
+        let start = self.position;
+        self.position += 1;
+        let name = self.take_while(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        let mut attrs = Vec::new();
+        loop {
+            self.skip_whitespaces();
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Ok(Token::Open {
+                    name,
+                    attrs,
+                    self_closing: true,
+                });
+            } else if self.rest().starts_with('>') {
+                self.position += 1;
+                return Ok(Token::Open {
+                    name,
+                    attrs,
+                    self_closing: false,
+                });
+            } else if self.rest().is_empty() {
+                Err(self.error(start, "Element must end with `>` or `/>`."))?
+            }
+            attrs.push(self.attribute()?);
+        }
+    }
+
+    fn attribute(&mut self) -> Result<Attribute> {
+        let start = self.position;
+        let full_name =
+            self.take_while(|c| !c.is_whitespace() && !matches!(c, '=' | '>' | '/' | '{'));
+        if full_name.is_empty() {
+            self.position += 1;
+            Err(self.error(start, "Can't parse attribute."))?
+        }
+        let (namespace, name) = match full_name.split_once(':') {
+            Some((namespace, name)) => (namespace.to_string(), name.to_string()),
+            None => (String::new(), full_name),
+        };
+        self.skip_whitespaces();
+        if !self.rest().starts_with('=') {
+            // <... name>
+            return Ok(Attribute {
+                name,
+                namespace,
+                expr: AttrExprType::String(String::new()),
+            });
+        }
+        self.position += 1;
+        self.skip_whitespaces();
+        let expr = match self.rest().chars().next() {
+            // <... name="value">
+            Some(quote @ ('"' | '\'')) => {
+                self.position += 1;
+                let value = self.take_while(|c| c != quote);
+                if self.rest().is_empty() {
+                    Err(self.error(start, "Attribute's value must be closed."))?
+                }
+                self.position += 1;
+                AttrExprType::String(value)
+            }
+            // <... name={some rust code}>
+            Some('{') => {
+                let block_start = self.position;
+                let stream = self.brace()?;
+                let span = self.span(block_start, self.position);
+                match single_ident(&stream) {
+                    Some(ident) => AttrExprType::Ident(ident),
+                    None => AttrExprType::Block(block_from_stream(stream, span)?),
+                }
+            }
+            // <... name=value>
+            _ => AttrExprType::String(
+                self.take_while(|c| !c.is_whitespace() && c != '>' && c != '/'),
+            ),
+        };
+        Ok(Attribute {
+            name,
+            namespace,
+            expr,
+        })
+    }
+
+    fn brace(&mut self) -> Result<TokenStream> {
+        //! Rust tokens between balanced braces, strings and chars can contain braces.
+        let start = self.position;
+        let mut depth = 0;
+        let mut chars = self.rest().char_indices().peekable();
+        let mut end = None;
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(i);
+                        break;
+                    }
+                }
+                '"' => {
+                    while let Some((_, c)) = chars.next() {
+                        match c {
+                            '\\' => {
+                                chars.next();
+                            }
+                            '"' => break,
+                            _ => (),
+                        }
+                    }
+                }
+                // Char literal (not a lifetime) : 'c' or '\n'
+                '\'' => {
+                    let mut ahead = chars.clone();
+                    let is_char = match ahead.next() {
+                        Some((_, '\\')) => true,
+                        Some(_) => matches!(ahead.next(), Some((_, '\''))),
+                        None => false,
+                    };
+                    if is_char {
+                        while let Some((_, c)) = chars.next() {
+                            match c {
+                                '\\' => {
+                                    chars.next();
+                                }
+                                '\'' => break,
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        let end = match end {
+            Some(end) => end,
+            None => {
+                self.position = self.source.len();
+                Err(self.error(start, "Block must be closed (`}`)."))?
+            }
+        };
+        let content = self.rest()[1..end].to_string();
+        self.position += end + 1;
+        let span = self.span(start, self.position);
+        let stream = TokenStream::from_str(&content)
+            .map_err(|err| Error::new(span, format!("Can't lex rust code : {err}")))?;
+        Ok(super::replace_sigils(respan(stream, span), true))
+    }
+}
+
+fn respan(stream: TokenStream, span: Span) -> TokenStream {
+    stream
+        .into_iter()
+        .map(|mut tree| {
+            if let TokenTree::Group(group) = &tree {
+                let mut respanned = Group::new(group.delimiter(), respan(group.stream(), span));
+                respanned.set_span(span);
+                tree = TokenTree::Group(respanned);
+            } else {
+                tree.set_span(span);
+            }
+            tree
+        })
+        .collect()
+}
+
+fn single_ident(stream: &TokenStream) -> Option<Ident> {
+    let mut trees = stream.clone().into_iter();
+    match (trees.next(), trees.next()) {
+        (Some(TokenTree::Ident(ident)), None) => Some(ident),
+        _ => None,
+    }
+}
+
+fn block_from_stream(stream: TokenStream, span: Span) -> Result<Block> {
+    let mut group = Group::new(Delimiter::Brace, stream);
+    group.set_span(span);
+    syn::parse2(TokenTree::Group(group).into())
+        .map_err(|err| Error::new(err.span(), format!("Error in rust block : {}", err)))
+}
+
+/// Blocks continuing or closing a parent block (ex: `{else}`, `{case _}` or `{/if}`).
+enum Branch {
+    Close(Ident),
+    ElseIf(Expr),
+    Else,
+    Case(Pat, Option<Expr>),
+    Then(Pat),
+    Catch(Pat),
+}
+
+fn is_branch(stream: &TokenStream) -> bool {
+    match stream.clone().into_iter().next() {
+        Some(TokenTree::Punct(punct)) => punct.as_char() == '/',
+        Some(TokenTree::Ident(ident)) => {
+            matches!(
+                ident.to_string().as_str(),
+                "else" | "case" | "then" | "catch"
+            )
+        }
+        _ => false,
+    }
+}
+
+fn parse_branch(input: ParseStream) -> Result<Branch> {
+    if input.peek(Token![/]) {
+        input.parse::<Token![/]>()?;
+        Ok(Branch::Close(input.call(Ident::parse_any)?))
+    } else if input.peek(Token![else]) {
+        input.parse::<Token![else]>()?;
+        if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            Ok(Branch::ElseIf(parse_expr_without_eager_brace(input)?))
+        } else {
+            Ok(Branch::Else)
+        }
+    } else if input.peek(kw::case) {
+        input.parse::<kw::case>()?;
+        let pat = utils::multi_pat_with_leading_vert(input)?;
+        let guard = if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            Some(parse_expr_without_eager_brace(input)?)
+        } else {
+            None
+        };
+        Ok(Branch::Case(pat, guard))
+    } else if input.peek(kw::then) {
+        input.parse::<kw::then>()?;
+        Ok(Branch::Then(parse_settled_pat(input)?))
+    } else {
+        input.parse::<kw::catch>()?;
+        Ok(Branch::Catch(parse_settled_pat(input)?))
+    }
+}
+
+struct TreeBuilder {
+    tokens: Peekable<IntoIter<(Token, Span)>>,
+}
+
+impl TreeBuilder {
+    fn new(tokens: Vec<(Token, Span)>) -> Self {
+        Self {
+            tokens: tokens.into_iter().peekable(),
+        }
+    }
+
+    fn root(mut self) -> Result<Vec<Element>> {
+        let elements = self.children()?;
+        match self.tokens.next() {
+            None => Ok(elements),
+            Some((Token::Close(name), span)) if is_void_element(&name) => {
+                Err(void_closing_error(span, &name))
+            }
+            Some((Token::Close(_), span)) => Err(Error::new(span, INNER_ERROR)),
+            Some((_, span)) => Err(Error::new(
+                span,
+                "This block must follow an opening block (ex: `{if ...}`).",
+            )),
+        }
+    }
+
+    fn children(&mut self) -> Result<Vec<Element>> {
+        //! Elements until a closing tag, a branch or the end of the template.
+        let mut children = Vec::new();
+        while let Some((token, _)) = self.tokens.peek() {
+            match token {
+                Token::Close(_) => break,
+                Token::Brace(stream) if is_branch(stream) => break,
+                _ => children.push(self.element()?),
+            }
+        }
+        Ok(children)
+    }
+
+    fn element(&mut self) -> Result<Element> {
+        let (token, span) = self.tokens.next().expect("Element is parsed after a peek.");
+        Ok(match token {
+            Token::Text(text) => Element::Text(text),
+            Token::Comment(comment) => Element::Comment(comment),
+            Token::Open {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                let children = if self_closing || is_void_element(&name) {
+                    Vec::new()
+                } else {
+                    let children = self.children()?;
+                    match self.tokens.next() {
+                        Some((Token::Close(close_name), _)) if close_name == name => (),
+                        Some((Token::Close(close_name), close_span))
+                            if is_void_element(&close_name) =>
+                        {
+                            Err(void_closing_error(close_span, &close_name))?
+                        }
+                        Some((Token::Close(_), close_span)) => {
+                            double_error(
+                                &close_span,
+                                "Closing element's name does not match an opening element's name.",
+                                &span,
+                                "No closing element's name match this elements name.",
+                            )?;
+                        }
+                        _ => Err(Error::new(span, "Element is not closed."))?,
+                    }
+                    if is_component(&name) && !children.is_empty() {
+                        Err(Error::new(span, COMPONENT_CHILDREN_ERROR))?;
+                    }
+                    children
+                };
+                Element::Classic(Classic {
+                    name,
+                    attrs,
+                    children,
+                })
+            }
+            Token::Close(_) => unreachable!("Closing tags stop children."),
+            Token::Brace(stream) => Element::ExprElement(self.expr_element(stream, span)?),
+        })
+    }
+
+    fn next_branch(&mut self, open_span: Span, keyword: &str) -> Result<(Branch, Span)> {
+        match self.tokens.next() {
+            Some((Token::Brace(stream), span)) => {
+                let branch = parse_branch.parse2(stream)?;
+                if let Branch::Close(ref close) = branch {
+                    if close != keyword {
+                        double_error(
+                            &open_span,
+                            "Closing element's name does not match an opening element's name.",
+                            &span,
+                            "No closing element's name match this elements name.",
+                        )?;
+                    }
+                }
+                Ok((branch, span))
+            }
+            Some((_, span)) => {
+                let mut error =
+                    Error::new(open_span, format!("Block must be closed ({{/{keyword}}})."));
+                error.combine(Error::new(
+                    span,
+                    format!("Consider to insert a closing block before this tag ({{/{keyword}}})."),
+                ));
+                Err(error)
+            }
+            None => Err(Error::new(
+                open_span,
+                format!("Block must be closed ({{/{keyword}}})."),
+            )),
+        }
+    }
+
+    fn unexpected(&self, open_span: Span, span: Span, keyword: &str) -> Error {
+        let mut error = Error::new(
+            open_span,
+            format!("This block can't contain this branch, close it first ({{/{keyword}}})."),
+        );
+        error.combine(Error::new(span, "Unexpected branch."));
+        error
+    }
+
+    fn expr_element(&mut self, stream: TokenStream, span: Span) -> Result<ExprElement> {
+        let keyword = match stream.clone().into_iter().next() {
+            Some(TokenTree::Ident(ident)) => ident.to_string(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "for" => {
+                let ForLoopWithoutBlock { expr, key } = syn::parse2(stream)?;
+                let children = self.children()?;
+                match self.next_branch(span, "for")? {
+                    (Branch::Close(_), _) => (),
+                    (_, branch_span) => return Err(self.unexpected(span, branch_span, "for")),
+                }
+                Ok(ExprElement::For {
+                    expr,
+                    key,
+                    children,
+                })
+            }
+            "if" => {
+                let mut expr = (|input: ParseStream| {
+                    input.parse::<Token![if]>()?;
+                    parse_expr_without_eager_brace(input)
+                })
+                .parse2(stream)?;
+                let mut conditions = Vec::new();
+                let mut else_reached = false;
+                loop {
+                    let children = self.children()?;
+                    conditions.push(Condition { expr, children });
+                    match self.next_branch(span, "if")? {
+                        (Branch::Close(_), _) => break,
+                        (Branch::ElseIf(else_if), _) if !else_reached => expr = else_if,
+                        (Branch::Else, _) if !else_reached => {
+                            else_reached = true;
+                            expr = syn::parse_str("true")?;
+                        }
+                        (_, branch_span) => return Err(self.unexpected(span, branch_span, "if")),
+                    }
+                }
+                Ok(ExprElement::If { conditions })
+            }
+            "match" => {
+                let expr = (|input: ParseStream| {
+                    input.parse::<Token![match]>()?;
+                    parse_expr_without_eager_brace(input)
+                })
+                .parse2(stream)?;
+                if !self.children()?.is_empty() {
+                    Err(Error::new(
+                        span,
+                        "Match's children must follow a case ({case Pattern}).",
+                    ))?
+                }
+                let mut cases = Vec::new();
+                loop {
+                    match self.next_branch(span, "match")? {
+                        (Branch::Close(_), _) => break,
+                        (Branch::Case(pat, guard), _) => cases.push(Case {
+                            pat,
+                            guard,
+                            children: self.children()?,
+                        }),
+                        (_, branch_span) => {
+                            return Err(self.unexpected(span, branch_span, "match"))
+                        }
+                    }
+                }
+                Ok(ExprElement::Match { expr, cases })
+            }
+            "await" => {
+                let future = (|input: ParseStream| {
+                    input.parse::<Token![await]>()?;
+                    input.parse::<Expr>()
+                })
+                .parse2(stream)?;
+                let pending = self.children()?;
+                let mut then = None;
+                let mut catch = None;
+                loop {
+                    match self.next_branch(span, "await")? {
+                        (Branch::Close(_), _) => break,
+                        (Branch::Then(pat), _) if then.is_none() && catch.is_none() => {
+                            then = Some(Settled {
+                                pat,
+                                children: self.children()?,
+                            })
+                        }
+                        (Branch::Catch(pat), _) if catch.is_none() => {
+                            catch = Some(Settled {
+                                pat,
+                                children: self.children()?,
+                            })
+                        }
+                        (_, branch_span) => {
+                            return Err(self.unexpected(span, branch_span, "await"))
+                        }
+                    }
+                }
+                Ok(ExprElement::Await {
+                    future,
+                    pending,
+                    then,
+                    catch,
+                })
+            }
+            _ => match single_ident(&stream) {
+                Some(ident) => Ok(ExprElement::Ident(ident)),
+                None => match syn::parse2::<LitStr>(stream.clone()) {
+                    Ok(lit) => Ok(ExprElement::Lit(lit)),
+                    Err(_) => Ok(ExprElement::Block(block_from_stream(stream, span)?)),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Template;
+    use crate::html::{AttrExprType, Classic, Element, ExprElement};
+
+    #[test]
+    fn template_keeps_text() -> syn::Result<()> {
+        let Template(elements) = syn::parse_str(
+            r####"r#"
+            <p class="note" on:click={count += 1}>Don't panic, a < b #1 🚀 {count}</p>
+            "#"####,
+        )?;
+        match elements.as_slice() {
+            [Element::Classic(Classic {
+                name,
+                attrs,
+                children,
+            })] => {
+                assert_eq!(name, "p");
+                assert_eq!(attrs[0].expr, AttrExprType::String("note".to_string()));
+                assert_eq!(attrs[1].namespace, "on");
+                assert!(matches!(attrs[1].expr, AttrExprType::Block(_)));
+                match children.as_slice() {
+                    [Element::Text(text), Element::ExprElement(ExprElement::Ident(ident))] => {
+                        assert_eq!(text, "Don't panic, a < b #1 🚀 ");
+                        assert_eq!(ident.to_string(), "count");
+                    }
+                    _ => panic!("Children don't match."),
+                }
+            }
+            _ => panic!("Elements don't match."),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn template_blocks() -> syn::Result<()> {
+        let Template(elements) = syn::parse_str(
+            r####"template = r#"
+            {if value == "}"}<br>{else}<i>{'{'}</i>{/if}
+            {for item in items}<li>{item}</li>{/for}
+            "#"####,
+        )?;
+        match elements.as_slice() {
+            [Element::ExprElement(ExprElement::If { conditions }), Element::ExprElement(ExprElement::For { children, .. })] =>
+            {
+                assert_eq!(conditions.len(), 2);
+                assert_eq!(children.len(), 1);
+            }
+            _ => panic!("Elements don't match."),
+        }
+        assert!(syn::parse_str::<Template>(r#""{if a}<p></p>""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn template_component_children() {
+        assert!(syn::parse_str::<Template>(r#""<div><Card/></div>""#).is_ok());
+        match syn::parse_str::<Template>(r#""<Card><p>hi</p></Card>""#) {
+            Err(error) => assert!(error.to_string().contains("can't have children")),
+            Ok(_) => panic!("The children of a component are refused."),
+        }
+    }
+}
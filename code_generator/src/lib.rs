@@ -1,21 +1,22 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::ToTokens;
-use syn::*;
+use syn::{parse::Parser, *};
 
 mod check;
 mod html;
 mod state_block;
 mod template;
 mod thir;
+mod types;
 mod visitor;
 // use template::{block_from_templates, Imports, Macros, TemplateOnce};
 use template::Component;
 
 use crate::{
     check::check_ident_expr,
-    html::{Element, Root},
-    state_block::extract_locals,
+    html::{replace_store_sigils, Element, Root, Template},
+    state_block::{extract_locals, parse_state_block},
 };
 
 #[proc_macro]
@@ -34,25 +35,55 @@ pub fn full(block: TokenStream) -> TokenStream {
 }
 
 struct Full {
+    name: Option<Ident>,
     block: Block,
     elements: Vec<Element>,
 }
 
 impl syn::parse::Parse for Full {
     fn parse(input: syn::parse::ParseStream) -> Result<Self> {
-        let block: Block = input.parse()?;
+        // Child component : `full! { Counter { ... } <p>...</p> }`
+        let name: Option<Ident> = if input.peek(Ident) {
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        let block: Block = parse_state_block(input)?;
         println!("{:#?}", block);
-        let elements: Vec<Element> = input.parse::<Root>()?.0;
+        let markup = replace_store_sigils(input.parse()?);
+        // Raw-string template : `r#"..."#` or `template = r#"..."#`
+        let elements: Vec<Element> = (|input: syn::parse::ParseStream| {
+            if input.peek(LitStr) || (input.peek(Ident) && input.peek2(Token![=])) {
+                Ok(input.parse::<Template>()?.0)
+            } else {
+                Ok(input.parse::<Root>()?.0)
+            }
+        })
+        .parse2(markup)?;
         println!("ELEMENTS {:#?}", elements);
-        Ok(Self { block, elements })
+        Ok(Self {
+            name,
+            block,
+            elements,
+        })
     }
 }
 
 fn full_error_wrapper(input: proc_macro2::TokenStream) -> Result<TokenStream> {
-    let Full { block, elements } = parse2(input)?;
-    let mut details_locals = extract_locals(&block)?;
+    let Full {
+        name,
+        block,
+        elements,
+    } = parse2(input)?;
+    let source_file = proc_macro::Span::call_site().file();
+    let mut details_locals = extract_locals(&block, types::file_items(&source_file))?;
+    details_locals.source_file = source_file;
     check_ident_expr(&elements, &details_locals)?;
-    Ok(Component::new(&mut details_locals, &elements).to_token_stream())
+    let component = Component::new(name.as_ref(), &mut details_locals, &elements)?;
+    Ok(match name {
+        Some(name) => component.into_named_token_stream(&name),
+        None => component.to_token_stream(),
+    })
 }
 
 #[proc_macro]
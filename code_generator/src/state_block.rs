@@ -1,10 +1,83 @@
-use syn::{Block, Error, Expr, ExprClosure, Ident, Local, Pat, PatIdent, PatType, Result, Type};
+use std::collections::{HashMap, HashSet};
 
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::{quote, quote_spanned};
+use syn::{
+    braced,
+    parse::{ParseStream, Parser},
+    parse_quote, parse_str,
+    visit::Visit,
+    visit_mut::{self, VisitMut},
+    Block, Error, Expr, ExprCall, ExprClosure, ExprMacro, ExprPath, Ident, Local, Pat, PatIdent,
+    PatType, Result, Stmt, Type,
+};
+
+use crate::{
+    html::replace_code_store_sigils,
+    thir::{self, PatBinding, Types},
+    types::{self, FileItems},
+    visitor::IdentExtractor,
+};
+
+/// Types of the `stores` module, `$count` reads them instead of declaring a derived value.
+const STORE_TYPES: [&str; 3] = ["Writable", "Readable", "Derived"];
+
+/// `#[prop] let label: String = default;` : the value is given by the parent component.
+#[derive(Debug, Clone)]
+pub struct Prop {
+    pub default: Option<Expr>,
+}
+
+/// `let doubled = $ count * 2;` : recomputed when one of its dependencies is updated.
+#[derive(Debug, Clone)]
+pub struct Derived {
+    pub expr: Expr,
+    pub deps: HashSet<String>,
+}
+
+/// Statement of the state's block which isn't a declaration (ex: `save(count);`),
+/// run after the mount and after each update of its dependencies.
 #[derive(Debug)]
+pub struct Effect {
+    pub expr: Expr,
+    pub deps: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookKind {
+    OnMount,
+    BeforeUpdate,
+    AfterUpdate,
+    OnDestroy,
+}
+
+impl HookKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "on_mount" => Some(Self::OnMount),
+            "before_update" => Some(Self::BeforeUpdate),
+            "after_update" => Some(Self::AfterUpdate),
+            "on_destroy" => Some(Self::OnDestroy),
+            _ => None,
+        }
+    }
+}
+
+/// Lifecycle hook : `let on_mount = || ...;` or `#[on_mount] let start_timer = || ...;`,
+/// its body is called by the generated `mount`, `update` or `drop`.
+#[derive(Debug)]
+pub struct Hook {
+    pub kind: HookKind,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
 pub struct State {
     pub ident: Ident,
     pub ty: Type,
     pub local: Local,
+    pub prop: Option<Prop>,
+    pub derived: Option<Derived>,
 }
 
 #[derive(Debug)]
@@ -18,10 +91,160 @@ pub struct EventClosure {
 pub struct LocalDetails {
     pub states: Vec<State>,
     pub events_closures: Vec<EventClosure>,
-    pub block: String,
+    pub effects: Vec<Effect>,
+    pub hooks: Vec<Hook>,
+    /// `set_context(key, value);` calls, run with the state's declarations so
+    /// child components can get the context.
+    pub context_calls: Vec<Expr>,
+    /// File of the component, the THIR artefacts are cached under its name.
+    pub source_file: String,
+    /// Items of the component's file, typing the fields of struct patterns and telling
+    /// which methods write the states.
+    pub items: FileItems,
+    /// Types of the markup's type queries (or why they aren't found), by code.
+    pub type_queries: HashMap<String, Result<Types>>,
 }
 
 impl LocalDetails {
+    pub fn props(&self) -> impl Iterator<Item = (&State, &Prop)> {
+        self.states
+            .iter()
+            .filter_map(|state| state.prop.as_ref().map(|prop| (state, prop)))
+    }
+
+    pub fn deriveds(&self) -> impl Iterator<Item = (&State, &Derived)> {
+        self.states
+            .iter()
+            .filter_map(|state| state.derived.as_ref().map(|derived| (state, derived)))
+    }
+
+    pub fn hooks(&self, kind: HookKind) -> impl Iterator<Item = &Hook> {
+        self.hooks.iter().filter(move |hook| hook.kind == kind)
+    }
+
+    pub fn resolve_types(&mut self, mut codes: Vec<(String, Span)>) -> Result<()> {
+        //! Untyped states not resolved from the others (typed `_`) and the markup's type
+        //! queries are found by one rustc invocation.
+        codes.sort_by(|(code, _), (other, _)| code.cmp(other));
+        codes.dedup_by(|(code, _), (other, _)| code == other);
+        let states_code = self
+            .states
+            .iter()
+            .find(|state| is_pending(state))
+            .map(|state| (self.pending_states_code(), state.ident.span()));
+        let queries = codes
+            .iter()
+            .cloned()
+            .chain(states_code.clone())
+            .collect::<Vec<_>>();
+        if queries.is_empty() {
+            return Ok(());
+        }
+        let mut results =
+            thir::get_types_batch(&queries, &self.items.declarations, &self.source_file)
+                .into_iter();
+        for (code, _) in codes {
+            if let Some(result) = results.next() {
+                self.type_queries.insert(code, result);
+            }
+        }
+        let (types, reason) = match results.next() {
+            Some(Ok(types)) => (types, String::new()),
+            // Only a nightly rustc infers the other types, the annotation is the way on stable
+            Some(Err(error)) if error.to_string() == thir::NIGHTLY_NEEDED => {
+                (Types::default(), String::new())
+            }
+            Some(Err(error)) => (Types::default(), format!("\n{error}")),
+            None => (Types::default(), String::new()),
+        };
+        let states_code = states_code.map(|(code, _)| code).unwrap_or_default();
+        let mut errors = Vec::new();
+        for state in self.states.iter_mut().filter(|state| is_pending(state)) {
+            // The type of the state's expression, else of the last binding of its name
+            let ty = pending_state_type(&states_code, &types, &state.ident)
+                .or_else(|| {
+                    types
+                        .bindings
+                        .iter()
+                        .rev()
+                        .find(|binding| state.ident == binding.name)
+                        .map(|binding| binding.ty.as_str())
+                })
+                .and_then(|ty| parse_str::<Type>(ty).ok());
+            match ty {
+                Some(ty) => {
+                    // The local is typed like the state's field
+                    if let Pat::Type(PatType { ty: local_ty, .. }) = &mut state.local.pat {
+                        **local_ty = ty.clone();
+                    }
+                    state.ty = ty;
+                }
+                None => errors.push(Error::new(
+                    state.ident.span(),
+                    format!(
+                        "Can't find the type of `{0}`, add it (ex: `let {0}: u32 = ...`).{reason}",
+                        state.ident
+                    ),
+                )),
+            }
+        }
+        match errors.into_iter().reduce(|mut error, other| {
+            error.combine(other);
+            error
+        }) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read_states_code(&self, tokens: TokenStream) -> String {
+        //! Code declaring the states read by the tokens, other types may be unknown outside
+        //! of the crate. Tokens are used to also see the states read inside macros. Pending
+        //! states are declared with their initializer and the states it reads.
+        let mut idents = token_idents(tokens);
+        // A state only reads the previous ones
+        for state in self.states.iter().rev() {
+            if is_pending(state) && idents.contains(&state.ident) {
+                let local = &state.local;
+                idents.extend(token_idents(quote::quote!(#local)));
+            }
+        }
+        self.states
+            .iter()
+            .filter(|state| idents.contains(&state.ident))
+            .map(|state| match state {
+                State { local, .. } if is_pending(state) => quote::quote!(#local),
+                State { ident, ty, .. } => quote::quote!(let #ident: #ty = unimplemented!();),
+            })
+            .collect::<TokenStream>()
+            .to_string()
+    }
+
+    fn pending_states_code(&self) -> String {
+        //! Code declaring the pending states, then reading each one on its own line
+        //! (`total;`) to find their types, see `pending_state_type`.
+        let idents: Vec<&Ident> = self
+            .states
+            .iter()
+            .filter(|state| is_pending(state))
+            .map(|state| &state.ident)
+            .collect();
+        let mut code = self.read_states_code(quote::quote!(#(#idents)*));
+        for ident in idents {
+            code.push_str(&format!("\n{ident};"));
+        }
+        code
+    }
+
+    pub fn pat_bindings(&self, code: String, span: Span) -> Result<Vec<PatBinding>> {
+        //! Bindings of a markup's type query, found with the others or alone.
+        match self.type_queries.get(&code) {
+            Some(result) => result.clone().map(|types| types.bindings),
+            None => thir::get_types(code, span, &self.items.declarations, &self.source_file)
+                .map(|types| types.bindings),
+        }
+    }
+
     pub fn get_ident_modifier(&self, state_ident: &str) -> super::visitor::IdentModifier {
         println!(
             "LocalDetails.states.idents : {:?}",
@@ -30,13 +253,15 @@ impl LocalDetails {
                 .map(|State { ident, .. }| ident.to_string())
                 .collect::<Vec<_>>()
         );
-        super::visitor::IdentModifier::new(
+        let mut ident_modifier = super::visitor::IdentModifier::new(
             self.states
                 .iter()
                 .map(|State { ident, .. }| ident.to_string())
                 .collect(),
             state_ident.to_string(),
-        )
+        );
+        ident_modifier.receivers = types::Receivers::new(&self.states, &self.items);
+        ident_modifier
     }
 
     pub fn states_contains_ident(&self, ident: &Ident) -> Result<()> {
@@ -69,19 +294,132 @@ impl LocalDetails {
     }
 }
 
-// fn extract_locals(block: &Block) -> Vec<LocalDetails> {
-pub fn extract_locals(block: &Block) -> Result<LocalDetails> {
-    let mut details = LocalDetails::default();
-    let locals: Vec<Local> = block
-        .stmts
-        .iter()
-        .filter_map(|statement| match statement {
-            syn::Stmt::Local(local) => Some(local),
+pub fn parse_state_block(input: ParseStream) -> Result<Block> {
+    //! Derived declarations (`let doubled = $ count * 2;`) aren't valid Rust,
+    //! they are rewritten as `#[derived] let doubled = count * 2;` before parsing the block.
+    //! The other sigils read stores (`$count` is `store!(count)`), so does the sigil
+    //! of a declaration when it's followed by a store (`let start: u32 = $count;`).
+    let content;
+    let brace_token = braced!(content in input);
+    let tokens: TokenStream = content.parse()?;
+    let mut stmts = TokenStream::new();
+    let mut stmt = Vec::new();
+    let mut stores = HashSet::new();
+    for token in tokens {
+        let end_of_stmt = matches!(token, TokenTree::Punct(ref punct) if punct.as_char() == ';');
+        stmt.push(token);
+        if end_of_stmt {
+            stmts.extend(mark_derived(std::mem::take(&mut stmt), &mut stores));
+        }
+    }
+    stmts.extend(mark_derived(stmt, &mut stores));
+    Ok(Block {
+        brace_token,
+        stmts: Block::parse_within.parse2(stmts)?,
+    })
+}
+
+fn mark_derived(mut stmt: Vec<TokenTree>, stores: &mut HashSet<String>) -> TokenStream {
+    //! `stores` are the stores declared by the previous statements,
+    //! this one is added to them when it declares a store.
+    // Only the `=` of the declaration : `let add = |_| total = $count;` isn't derived
+    let dollar = (0..stmt.len().saturating_sub(2)).find(|&position| {
+        match (&stmt[position], &stmt[position + 1], &stmt[position + 2]) {
+            (TokenTree::Punct(eq), TokenTree::Punct(dollar), next) => {
+                eq.as_char() == '='
+                    && dollar.as_char() == '$'
+                    && !matches!(next, TokenTree::Ident(ident) if stores.contains(&ident.to_string()))
+                    && is_declaration(&stmt[..position])
+            }
+            _ => false,
+        }
+    });
+    let marked = match dollar {
+        Some(position) => {
+            let span = stmt.remove(position + 1).span();
+            let mut marked = quote_spanned!(span=> #[derived]);
+            marked.extend(stmt);
+            marked
+        }
+        None => stmt.into_iter().collect(),
+    };
+    let marked = replace_code_store_sigils(marked);
+    if let Ok(Stmt::Local(local)) = syn::parse2(marked.clone()) {
+        stores.extend(declared_store(&local));
+    }
+    marked
+}
+
+fn declared_store(local: &Local) -> Option<String> {
+    //! `let count: Writable<u32> = ...;` or `let count = stores::Writable::new(0);`.
+    let is_store = |path: &syn::Path| {
+        path.segments
+            .iter()
+            .any(|segment| STORE_TYPES.contains(&segment.ident.to_string().as_str()))
+    };
+    match &local.pat {
+        Pat::Type(PatType { pat, ty, .. }) => match (&**pat, &**ty) {
+            (Pat::Ident(PatIdent { ident, .. }), Type::Path(path)) if is_store(&path.path) => {
+                Some(ident.to_string())
+            }
             _ => None,
-        })
-        .cloned()
-        .collect();
-    for local in locals {
+        },
+        Pat::Ident(PatIdent { ident, .. }) => match local.init.as_ref().map(|(_, expr)| &**expr) {
+            Some(Expr::Call(ExprCall { func, .. })) => match &**func {
+                Expr::Path(ExprPath { path, .. }) if is_store(path) => Some(ident.to_string()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_declaration(tokens: &[TokenTree]) -> bool {
+    //! `let doubled: u32` without its initializer.
+    let tokens: TokenStream = tokens.iter().cloned().collect();
+    matches!(
+        syn::parse2::<Stmt>(quote!(#tokens;)),
+        Ok(Stmt::Local(Local { init: None, .. }))
+    )
+}
+
+// fn extract_locals(block: &Block) -> Vec<LocalDetails> {
+pub fn extract_locals(block: &Block, items: FileItems) -> Result<LocalDetails> {
+    let mut details = LocalDetails {
+        items,
+        ..Default::default()
+    };
+    let mut effects = Vec::new();
+    for stmt in &block.stmts {
+        let mut local = match stmt {
+            Stmt::Local(local) => local.clone(),
+            Stmt::Expr(expr) | Stmt::Semi(expr, _) if is_set_context(expr) => {
+                details.context_calls.push(expr.clone());
+                continue;
+            }
+            Stmt::Expr(expr) | Stmt::Semi(expr, _) => {
+                effects.push(expr.clone());
+                continue;
+            }
+            Stmt::Item(item) => Err(Error::new_spanned(
+                item,
+                "Items are not handled in state's block.",
+            ))?,
+        };
+        if let Some((_, ref mut init)) = local.init {
+            InitStores.visit_expr_mut(init);
+        }
+        if let Some(hook) = take_hook(&mut local)? {
+            details.hooks.push(hook);
+            continue;
+        }
+        let is_prop = take_attr(&mut local, "prop");
+        if take_attr(&mut local, "derived") {
+            let state = derived_state(&details, local)?;
+            details.states.push(state);
+            continue;
+        }
         match local {
             Local {
                 pat:
@@ -94,35 +432,96 @@ pub fn extract_locals(block: &Block) -> Result<LocalDetails> {
             } => {
                 // assert_eq!(quote::quote!(#path_type).to_string(), "u32");
                 match *pat_ident.clone() {
+                    Pat::Ident(PatIdent { ident, .. }) if is_prop => {
+                        // The value comes from the `Props` given to `State::new`
+                        let ty = *path_type.clone();
+                        details.states.push(State {
+                            local: prop_local(&ident, &ty),
+                            ty,
+                            ident,
+                            prop: Some(Prop {
+                                default: local.init.map(|(_, expr)| *expr),
+                            }),
+                            derived: None,
+                        });
+                    }
                     Pat::Ident(PatIdent { ident, .. }) => {
                         details.states.push(State {
                             ty: *path_type.clone(),
                             ident: ident.clone(),
                             local: local.clone(),
+                            prop: None,
+                            derived: None,
                         });
                     }
-                    _ => panic!("FIXME: get ident from PatIdent"),
+                    pat if is_prop => Err(Error::new_spanned(pat, "A prop must be an ident."))?,
+                    pat => {
+                        let states = destructured_states(&local, &pat, path_type, &details.items)?;
+                        details.states.extend(states);
+                    }
                 };
             }
+            Local {
+                pat: Pat::Ident(PatIdent { ref ident, .. }),
+                ..
+            } if is_prop => Err(Error::new(ident.span(), "A prop must have a type."))?,
             Local {
                 pat: Pat::Ident(PatIdent { ref ident, .. }),
                 ref init,
                 ..
             } => {
-                if let Some((_, boxed_expr)) = init {
-                    if let Expr::Closure(ref closure) = **boxed_expr {
-                        details.events_closures.push(EventClosure {
-                            ident: ident.clone(),
-                            closure: closure.clone(),
-                            local: local.clone(),
-                        });
-                        continue;
-                    }
+                let expr = match init {
+                    Some((_, boxed_expr)) => boxed_expr,
+                    None => Err(Error::new(
+                        ident.span(),
+                        "You must add a type or a value to infer it from.",
+                    ))?,
+                };
+                if let Expr::Closure(ref closure) = **expr {
+                    details.events_closures.push(EventClosure {
+                        ident: ident.clone(),
+                        closure: closure.clone(),
+                        local: local.clone(),
+                    });
+                    continue;
                 }
-                Err(Error::new(
-                    ident.span(),
-                    "You must add a type. (Automatic type detection is not yet implemented)",
-                ))?;
+                let ty = infer_type(&details, expr);
+                // The local is typed like the state's field
+                let mut typed_local = local.clone();
+                typed_local.pat = Pat::Type(PatType {
+                    attrs: Vec::new(),
+                    pat: Box::new(local.pat.clone()),
+                    colon_token: Default::default(),
+                    ty: Box::new(ty.clone()),
+                });
+                details.states.push(State {
+                    ident: ident.clone(),
+                    ty,
+                    local: typed_local,
+                    prop: None,
+                    derived: None,
+                });
+            }
+            Local {
+                pat: ref pat @ (Pat::Tuple(_) | Pat::Struct(_) | Pat::TupleStruct(_)),
+                init: Some((_, ref expr)),
+                ..
+            } if !is_prop => {
+                let ty = types::expr_type(expr, &details.states, &details.items).ok_or_else(|| {
+                    Error::new_spanned(
+                        pat,
+                        "Can't infer the type of the destructured value, add it (ex: `let (x, y): (i32, i32) = ..;`).",
+                    )
+                })?;
+                let mut typed_local = local.clone();
+                typed_local.pat = Pat::Type(PatType {
+                    attrs: Vec::new(),
+                    pat: Box::new(pat.clone()),
+                    colon_token: Default::default(),
+                    ty: Box::new(ty.clone()),
+                });
+                let states = destructured_states(&typed_local, pat, &ty, &details.items)?;
+                details.states.extend(states);
             }
             _ => Err(Error::new(
                 local.let_token.span,
@@ -130,11 +529,417 @@ pub fn extract_locals(block: &Block) -> Result<LocalDetails> {
             ))?,
         }
     }
-    details.block = details
-        .states
-        .iter()
-        .map(|State { local, .. }| quote::quote!(#local).to_string())
-        .collect::<Vec<_>>()
-        .join("\n");
+    // Effects run once every state is declared
+    details.effects = effects
+        .into_iter()
+        .map(|expr| Effect {
+            deps: state_deps(&expr, &details.states),
+            expr,
+        })
+        .collect();
     Ok(details)
 }
+
+fn derived_state(details: &LocalDetails, local: Local) -> Result<State> {
+    //! Dependencies are the states read by the expression, the type is found
+    //! by rustc when it isn't given.
+    let (ident, ty) = match local.pat {
+        Pat::Type(PatType {
+            ref pat, ref ty, ..
+        }) => match **pat {
+            Pat::Ident(PatIdent { ref ident, .. }) => (ident.clone(), Some(*ty.clone())),
+            _ => Err(Error::new(
+                local.let_token.span,
+                "A derived value must be an ident.",
+            ))?,
+        },
+        Pat::Ident(PatIdent { ref ident, .. }) => (ident.clone(), None),
+        _ => Err(Error::new(
+            local.let_token.span,
+            "A derived value must be an ident.",
+        ))?,
+    };
+    let expr = match local.init {
+        Some((_, ref expr)) => *expr.clone(),
+        None => Err(Error::new(
+            ident.span(),
+            "A derived value needs an expression (ex: `let doubled = $ count * 2;`).",
+        ))?,
+    };
+
+    let deps = state_deps(&expr, &details.states);
+    let ty = match ty {
+        Some(ty) => ty,
+        None => infer_type(details, &expr),
+    };
+
+    Ok(State {
+        local: parse_local(quote::quote!(let #ident: #ty = #expr;)),
+        ident,
+        ty,
+        prop: None,
+        derived: Some(Derived { expr, deps }),
+    })
+}
+
+fn destructured_states(
+    local: &Local,
+    pat: &Pat,
+    ty: &Type,
+    items: &FileItems,
+) -> Result<Vec<State>> {
+    //! Each binding of a destructuring local is a state of its own, so they are updated
+    //! apart. The first state declares them all, the next ones only rebind their value.
+    let bindings = types::pat_bindings(pat, ty, items).ok_or_else(|| {
+        Error::new_spanned(
+            pat,
+            "Can't find the type of the bindings, only tuple patterns and patterns of the structs of this file are supported.",
+        )
+    })?;
+    if bindings.is_empty() {
+        Err(Error::new_spanned(
+            pat,
+            "This local has no binding to make a state of.",
+        ))?;
+    }
+    bindings
+        .into_iter()
+        .enumerate()
+        .map(|(index, (ident, ty))| {
+            if let Type::Infer(_) = ty {
+                Err(Error::new(
+                    ident.span(),
+                    format!("The type of `{ident}` must be given."),
+                ))?;
+            }
+            let local = match index {
+                0 => local.clone(),
+                _ => parse_local(quote::quote!(let #ident: #ty = #ident;)),
+            };
+            Ok(State {
+                ident,
+                ty,
+                local,
+                prop: None,
+                derived: None,
+            })
+        })
+        .collect()
+}
+
+fn infer_type(details: &LocalDetails, expr: &Expr) -> Type {
+    //! Type of an untyped state, resolved from the literals and the previous states.
+    //! Otherwise it's `_` until rustc finds it (see `LocalDetails::resolve_types`).
+    types::expr_type(expr, &details.states, &details.items).unwrap_or_else(|| parse_quote!(_))
+}
+
+fn is_pending(state: &State) -> bool {
+    matches!(state.ty, Type::Infer(_))
+}
+
+fn pending_state_type<'a>(code: &str, types: &'a Types, ident: &Ident) -> Option<&'a str> {
+    //! Type of the expression reading the state on its own line of the pending states' code.
+    let read = format!("{ident};");
+    let (line, _) = code
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| *line == read)
+        .last()?;
+    let line = line as u32 + 1;
+    let start = thir::Location { line, column: 1 };
+    let end = thir::Location {
+        line,
+        column: ident.to_string().chars().count() as u32 + 1,
+    };
+    types.expr_type(start, end)
+}
+
+fn token_idents(tokens: TokenStream) -> Vec<Ident> {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Ident(ident) => vec![ident],
+            TokenTree::Group(group) => token_idents(group.stream()),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn is_set_context(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(ExprCall { func, .. }) => match **func {
+            Expr::Path(ExprPath { ref path, .. }) => path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "set_context"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn state_deps(expr: &Expr, states: &[State]) -> HashSet<String> {
+    //! States read by an expression.
+    let mut extractor = IdentExtractor::new();
+    extractor.visit_expr(expr);
+    extractor
+        .idents
+        .iter()
+        .map(|ident| ident.to_string())
+        .filter(|name| states.iter().any(|state| state.ident == name))
+        .collect()
+}
+
+fn prop_local(ident: &Ident, ty: &Type) -> Local {
+    parse_local(quote::quote!(let #ident: #ty = props.#ident;))
+}
+
+fn parse_local(tokens: TokenStream) -> Local {
+    match parse_quote!(#tokens) {
+        Stmt::Local(local) => local,
+        _ => unreachable!(),
+    }
+}
+
+fn take_hook(local: &mut Local) -> Result<Option<Hook>> {
+    //! A hook is a closure without argument, named after the hook or with its attribute.
+    let attr_kind = ["on_mount", "before_update", "after_update", "on_destroy"]
+        .into_iter()
+        .find(|name| take_attr(local, name))
+        .and_then(HookKind::from_name);
+    let name_kind = match local.pat {
+        Pat::Ident(PatIdent { ref ident, .. }) => HookKind::from_name(&ident.to_string()),
+        _ => None,
+    };
+    let closure = match local.init {
+        Some((_, ref expr)) => match **expr {
+            Expr::Closure(ref closure) => Some(closure),
+            _ => None,
+        },
+        None => None,
+    };
+    match (attr_kind.or(name_kind), closure) {
+        (Some(kind), Some(closure)) if closure.inputs.is_empty() => Ok(Some(Hook {
+            kind,
+            body: *closure.body.clone(),
+        })),
+        (Some(_), Some(closure)) => Err(Error::new_spanned(
+            &closure.inputs,
+            "A lifecycle hook takes no argument.",
+        )),
+        (Some(_), None) if attr_kind.is_some() => Err(Error::new(
+            local.let_token.span,
+            "A lifecycle hook must be a closure (ex: `let on_mount = || start();`).",
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Replace `store!(count)` by `count.get()` in an initializer, the store is a local there.
+/// Closures are left to `StoreModifier`, they read the store from the state.
+struct InitStores;
+
+impl VisitMut for InitStores {
+    fn visit_expr_mut(&mut self, node: &mut Expr) {
+        match node {
+            Expr::Closure(_) => {}
+            Expr::Macro(ExprMacro { mac, .. }) if mac.path.is_ident("store") => {
+                if let Ok(ident) = mac.parse_body::<Ident>() {
+                    *node = parse_quote!(#ident.get());
+                }
+            }
+            _ => visit_mut::visit_expr_mut(self, node),
+        }
+    }
+}
+
+fn take_attr(local: &mut Local, name: &str) -> bool {
+    //! Remove `#[name]` from the local's attributes, return whether it was there.
+    let len = local.attrs.len();
+    local.attrs.retain(|attr| !attr.path.is_ident(name));
+    local.attrs.len() != len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_locals, parse_state_block, FileItems, HookKind};
+    use std::collections::HashSet;
+    use syn::parse::Parser;
+
+    #[test]
+    fn derived_declaration() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let count: u32 = 1;
+                let doubled: u32 = $ count * 2;
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let (state, derived) = details.deriveds().next().expect("doubled is derived");
+        assert_eq!(state.ident, "doubled");
+        assert_eq!(derived.deps, HashSet::from(["count".to_string()]));
+        Ok(())
+    }
+
+    #[test]
+    fn store_sigil_in_initializer() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let count: Writable<u32> = Writable::new(0);
+                let total: u32 = 0;
+                let add = |_| total = $count + 1;
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        assert_eq!(details.deriveds().count(), 0);
+        let closure = &details.events_closures[0].closure;
+        assert_eq!(
+            quote::quote!(#closure).to_string(),
+            "| _ | total = store ! (count) + 1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn store_read_in_declaration() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let count: Writable<u32> = Writable::new(0);
+                let start: u32 = $count;
+                let doubled: u32 = $ start * 2;
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let deriveds: Vec<_> = details.deriveds().map(|(state, _)| &state.ident).collect();
+        assert_eq!(deriveds, ["doubled"]);
+        let start = &details.states[1].local;
+        assert_eq!(
+            quote::quote!(#start).to_string(),
+            "let start : u32 = count . get () ;"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn called_state_types() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let total = compute();
+                let todo = Todo::new();
+                let labels = vec![todo.label.clone()];
+            }",
+        )?;
+        let items = syn::parse_file(
+            "fn compute() -> u32 { 3 }
+            struct Todo { label: String }
+            impl Todo { fn new() -> Self { todo!() } }",
+        )?;
+        let details = extract_locals(&block, FileItems::from_items(&items.items))?;
+        let types = details
+            .states
+            .iter()
+            .map(|state| {
+                let ty = &state.ty;
+                quote::quote!(#ty).to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["u32", "Todo", "Vec < String >"]);
+        Ok(())
+    }
+
+    #[test]
+    fn literal_types() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            r#"{
+                let mut count = 0;
+                let step = 2u8;
+                let name = "Bob".to_string();
+            }"#,
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let types = details
+            .states
+            .iter()
+            .map(|state| {
+                let ty = &state.ty;
+                quote::quote!(#ty).to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(types, ["i32", "u8", "String"]);
+        Ok(())
+    }
+
+    #[test]
+    fn destructured_states() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            r#"{
+                let (x, mut y): (i32, u8) = (0, 1);
+                let Point { label, .. }: Point = Point::default();
+                let (first, last) = ("Ada".to_string(), 2u8);
+            }"#,
+        )?;
+        let items = syn::parse_file("struct Point { x: i32, label: String }")?.items;
+        let details = extract_locals(&block, FileItems::from_items(&items))?;
+        let states = details
+            .states
+            .iter()
+            .map(|state| {
+                let (ident, ty, local) = (&state.ident, &state.ty, &state.local);
+                (
+                    format!("{ident}: {}", quote::quote!(#ty)),
+                    quote::quote!(#local).to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let idents = states.iter().map(|(ident, _)| ident).collect::<Vec<_>>();
+        assert_eq!(
+            idents,
+            [
+                "x: i32",
+                "y: u8",
+                "label: String",
+                "first: String",
+                "last: u8"
+            ]
+        );
+        assert_eq!(states[1].1, "let y : u8 = y ;");
+
+        let block = parse_state_block.parse_str("{ let (a, b) = pair(); }")?;
+        assert!(extract_locals(&block, FileItems::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn effect_statement() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let count: u32 = 1;
+                save(count);
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        assert_eq!(details.effects.len(), 1);
+        assert_eq!(
+            details.effects[0].deps,
+            HashSet::from(["count".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lifecycle_hooks() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let on_mount = || start();
+                #[on_destroy]
+                let stop_timer = || stop();
+                let add = |_| start();
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        assert_eq!(details.hooks(HookKind::OnMount).count(), 1);
+        assert_eq!(details.hooks(HookKind::OnDestroy).count(), 1);
+        assert_eq!(details.events_closures.len(), 1);
+        Ok(())
+    }
+}
@@ -1,11 +1,11 @@
 use quote::quote;
 pub use sailfish::TemplateOnce;
-use syn::{visit_mut::VisitMut, Block, Expr, ExprBlock, Ident};
+use syn::{visit_mut::VisitMut, Block, Expr, ExprBlock, ExprClosure, Ident, Stmt};
 
 use super::component::clean_up_generated;
 use crate::{
     state_block::{EventClosure, State},
-    visitor::IdentModifier,
+    visitor::{IdentModifier, StoreModifier},
 };
 
 #[derive(TemplateOnce)]
@@ -17,6 +17,11 @@ pub struct ClosureBindInputTemplate {
     insert_updated_ident: String,
 }
 
+/// Types of the states bound with `value_as_number`.
+const NUMBER_TYPES: [&str; 12] = [
+    "u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize", "f32", "f64",
+];
+
 pub struct ClosureBindInput {
     pub callback_on_change: (String, String),
     pub init_value: String,
@@ -27,29 +32,16 @@ impl ClosureBindInput {
     pub fn new(element_name: &str, init_ident: String, state: &State) -> ClosureBindInput {
         println!("Closure bind template");
         let name = &format!("{element_name}_bind_value");
-        let init_value;
-        let update_value;
 
-        let mut number_type = None;
+        // Numbers are set as numbers, the other values are displayed
         let State { ident, ty, .. } = state;
         let ty = quote!(#ty).to_string();
-        let is_number = vec![
-            "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64",
-        ]
-        .iter()
-        .cloned()
-        .collect::<String>()
-        .contains(&ty);
-
-        let value;
-        if is_number {
-            number_type = Some(ty.clone());
-            value = format!("state.borrow().{} as f64", init_ident);
-        } else {
-            value = format!("state.borrow().{}", init_ident);
-        }
-        init_value = format!("self.{}.set_value_as_number(self.{});", element_name, value);
-        update_value = format!("self.{}.set_value_as_number(self.{});", element_name, value);
+        let number_type = NUMBER_TYPES.contains(&ty.as_str()).then(|| ty.clone());
+        let value = format!("self.state.borrow().{init_ident}");
+        let set_value = match number_type {
+            Some(_) => format!("self.{element_name}.set_value_as_number({value} as f64);"),
+            None => format!(r#"self.{element_name}.set_value(&format!("{{}}", {value}));"#),
+        };
 
         let template = ClosureBindInputTemplate {
             name: name.to_string(),
@@ -64,8 +56,8 @@ impl ClosureBindInput {
                 name.to_string(),
                 clean_up_generated(template.render_once().unwrap()),
             ),
-            init_value,
-            update_value,
+            init_value: set_value.clone(),
+            update_value: set_value,
         }
     }
 }
@@ -83,14 +75,49 @@ impl ClosureTemplate {
         event_closure: &mut EventClosure,
         ident_modifier: &mut IdentModifier,
     ) -> (String, String) {
-        ident_modifier.visit_expr_closure_mut(&mut event_closure.closure);
-        let name = event_closure.ident.to_string();
+        Self::string_from_closure(
+            event_closure.ident.to_string(),
+            &mut event_closure.closure,
+            ident_modifier,
+        )
+    }
+
+    pub fn string_from_block(
+        name: String,
+        block: &Block,
+        ident_modifier: &mut IdentModifier,
+    ) -> (String, String) {
+        //! Anonymous callback from an inline handler (ex: `on:click={counter += 1}`).
+        let mut closure: ExprClosure = syn::parse_quote!(|_| #block);
+        Self::string_from_closure(name, &mut closure, ident_modifier)
+    }
+
+    fn string_from_closure(
+        name: String,
+        closure: &mut ExprClosure,
+        ident_modifier: &mut IdentModifier,
+    ) -> (String, String) {
+        // The state is borrowed once by the callback, see `closure.rs` template
+        let mut store_modifier = StoreModifier::new("s".to_string());
+        store_modifier.guard = Some("state".to_string());
+        store_modifier.visit_expr_closure_mut(closure);
+        ident_modifier.guard = Some("state".to_string());
+        ident_modifier.visit_expr_closure_mut(closure);
         let statements;
-        match *event_closure.closure.body.to_owned() {
+        match *closure.body.to_owned() {
             Expr::Block(ExprBlock {
                 block: Block { stmts, .. },
                 ..
-            }) => statements = stmts.iter().map(|s| quote!(#s).to_string()).collect(),
+            }) => {
+                statements = stmts
+                    .iter()
+                    .map(|s| match s {
+                        // The tail expression is followed by the dirty marking
+                        Stmt::Expr(expr) => quote!(#expr;).to_string(),
+                        s => quote!(#s).to_string(),
+                    })
+                    .collect()
+            }
             expr => statements = vec![quote!(#expr;).to_string()],
         }
         let mut modified_idents = Vec::new();
@@ -1,4 +1,8 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    rc::Rc,
+};
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
@@ -14,7 +18,7 @@ use crate::{
 };
 
 use super::{
-    nodes::{Dom, State as StateTemplate},
+    nodes::{type_queries, Dom, State as StateTemplate},
     ClosureTemplate,
 };
 
@@ -34,16 +38,45 @@ pub struct Component {
     pub dom: Dom,
 }
 
+/// Component used by another one (ex: `<Counter/>`), its items are generated in a module
+/// of this name and the runtime comes from the root component.
+#[derive(TemplateOnce)]
+#[template(path = "named_component.rs", escape = false)]
+pub struct NamedComponent {
+    pub dom: Dom,
+    pub name: String,
+}
+
+/// Keyed `{for}` algorithms of the runtime, apart from the template to be tested without the DOM.
+const KEYED_RUNTIME: &str = include_str!("../../templates/keyed.rs");
+
 pub fn clean_up_generated(generated: String) -> String {
     generated.as_str().replace("//ITEM ", "")
 }
 
+fn render_to_token_stream(template: impl TemplateOnce) -> TokenStream {
+    let generated: String = clean_up_generated(template.render_once().unwrap());
+    generated.parse().unwrap()
+}
+
 impl Component {
-    pub fn new(local_details: &mut LocalDetails, elements: &Vec<Element>) -> Self {
+    pub fn new(
+        name: Option<&Ident>,
+        local_details: &mut LocalDetails,
+        elements: &Vec<Element>,
+    ) -> syn::Result<Self> {
+        let queries = type_queries(elements, local_details);
+        local_details.resolve_types(queries)?;
         let init_block = local_details
             .states
             .iter()
             .map(|State { local, .. }| quote! {#local}.to_string())
+            .chain(
+                local_details
+                    .context_calls
+                    .iter()
+                    .map(|call| quote! {#call;}.to_string()),
+            )
             .collect::<Vec<String>>()
             .join("\n");
         let fields_type = local_details
@@ -92,14 +125,28 @@ impl Component {
             fields_type,
             fields_value,
             callbacks,
+            stores: BTreeSet::new(),
         }));
-        let dom = Dom::generate(elements, local_details, HashMap::new(), state.clone(), true);
-
-        Self { dom }
+        let mut dom = Dom::generate_root(
+            elements,
+            local_details,
+            HashMap::new(),
+            state.clone(),
+            name.is_none(),
+        );
+        match dom.take_errors() {
+            Some(error) => Err(error),
+            None => Ok(Self { dom }),
+        }
     }
     pub fn to_token_stream(self) -> TokenStream {
-        let generated: String = clean_up_generated(self.render_once().unwrap());
-        generated.parse().unwrap()
+        render_to_token_stream(self)
+    }
+    pub fn into_named_token_stream(self, name: &Ident) -> TokenStream {
+        render_to_token_stream(NamedComponent {
+            dom: self.dom,
+            name: name.to_string(),
+        })
     }
 }
 
@@ -131,3 +178,51 @@ pub fn gen_field_value_shorthand(ident: &str) -> String {
     punctuated_fields.push_punct(parse_str(",").unwrap());
     quote!(#punctuated_fields).to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    //! The keyed `{for}` algorithms of the runtime, without its DOM.
+    use std::{
+        collections::{HashMap, HashSet},
+        hash::Hash,
+    };
+
+    include!("../../templates/keyed.rs");
+
+    #[test]
+    fn increasing_subsequence() {
+        assert_eq!(longest_increasing_subsequence(&[]), Vec::<usize>::new());
+        assert_eq!(longest_increasing_subsequence(&[3, 2, 1]), [2]);
+        let sequence = [2, 0, 3, 1, 4];
+        let positions = longest_increasing_subsequence(&sequence);
+        assert_eq!(positions.len(), 3);
+        assert!(positions
+            .windows(2)
+            .all(|pair| sequence[pair[0]] < sequence[pair[1]]));
+        assert_eq!(longest_increasing_subsequence(&[0, 1, 2, 3]), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn keyed_reorder() {
+        let old = ["a", "b", "c", "d"];
+        let old_keys: Vec<Option<&&str>> = old.iter().map(Some).collect();
+        // "d" moves to the front, "b" is removed and "e" is created
+        let sources = keyed_sources(&old_keys, &[&"d", &"a", &"e", &"c"]);
+        assert_eq!(sources, [Some(3), Some(0), None, Some(2)]);
+        // Only "d" is moved, "a" and "c" keep their order
+        assert_eq!(stable_rows(&sources), HashSet::from([1, 3]));
+    }
+
+    #[test]
+    fn keyed_compares_keys() {
+        let old = [1u64, 2];
+        let old_keys: Vec<Option<&u64>> = old.iter().map(Some).collect();
+        // A repeated key reuses the old row once, the other one is created
+        assert_eq!(
+            keyed_sources(&old_keys, &[&2, &2, &3]),
+            [Some(1), None, None]
+        );
+        // An old key of another type isn't reused
+        assert_eq!(keyed_sources(&[None, Some(&2)], &[&1, &2]), [None, Some(1)]);
+    }
+}
@@ -1,20 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::{cell::RefCell, rc::Rc};
 
+use proc_macro2::Span;
 use proc_macro2_diagnostics::SpanDiagnosticExt;
-use quote::quote;
-use syn::{visit::Visit, visit_mut::VisitMut};
-use syn::{ExprForLoop, Ident};
+use quote::{quote, ToTokens};
+use syn::{spanned::Spanned, visit::Visit, visit_mut::VisitMut};
+use syn::{Block, Expr, ExprBlock, ExprForLoop, Ident, Type};
 
-use crate::thir;
+use crate::types;
 use crate::{
-    html::{AttrExprType, Attribute, Classic, Condition, Element, ExprElement},
-    state_block::LocalDetails,
-    visitor::ident::IdentExtractor,
+    html::{AttrExprType, Attribute, Case, Classic, Condition, Element, ExprElement, Settled},
+    state_block::{HookKind, LocalDetails},
+    thir::PatBinding,
+    visitor::{BindingExtractor, PropsModifier, StoreModifier},
 };
 pub use sailfish::TemplateOnce;
 
-use super::ClosureBindInput;
+use super::{ClosureBindInput, ClosureTemplate};
 
 macro_rules! format_ident {
     ($props:expr, $ident:ident: mut) => {{
@@ -54,6 +56,15 @@ pub struct State {
     pub fields_type: Vec<String>,
     pub fields_value: Vec<String>,
     pub callbacks: Vec<(String, String)>,
+    /// States read as stores in the markup (`$count`), the component subscribes to them.
+    pub stores: BTreeSet<String>,
+}
+
+/// Prop declared in a component's state block (ex: `#[prop] let label: String;`).
+pub struct ComponentProp {
+    pub ident: String,
+    pub ty: String,
+    pub default: Option<String>,
 }
 
 #[derive(TemplateOnce)]
@@ -64,14 +75,21 @@ pub struct Dom {
     pub mount_mounted: Vec<String>,
     pub update: Vec<String>,
     pub drop: Vec<String>,
+    pub move_nodes: Vec<String>,
+    pub listen_nodes: Vec<String>,
     // pub binded_callbacks: Vec<(String, String)>,
     pub fields: HashMap<String, String>,
     pub sub_doms: HashMap<String, Dom>,
     pub props: HashMap<String, String>,
+    /// Dom of a component (not a sub dom), it owns the state.
+    pub root: bool,
+    pub component_props: Vec<ComponentProp>,
     // data not used in template (most start with _)
     pub _state: Rc<RefCell<State>>,
     _tag_count: HashMap<String, usize>,
     _append_nodes: bool,
+    /// Errors of the markup found while generating, reported once the dom is complete.
+    _errors: Vec<syn::Error>,
 }
 
 impl Dom {
@@ -82,14 +100,29 @@ impl Dom {
             mount_mounted: Vec::new(),
             update: Vec::new(),
             drop: Vec::new(),
+            move_nodes: Vec::new(),
+            listen_nodes: Vec::new(),
             fields: HashMap::new(),
             props,
+            root: false,
+            component_props: Vec::new(),
             sub_doms: HashMap::new(),
             _state,
             _tag_count: HashMap::new(),
             _append_nodes: true,
+            _errors: Vec::new(),
         }
     }
+
+    pub fn take_errors(&mut self) -> Option<syn::Error> {
+        //! Errors of the dom and its sub doms, combined in one.
+        let mut errors = std::mem::take(&mut self._errors);
+        errors.extend(self.sub_doms.values_mut().filter_map(Dom::take_errors));
+        errors.into_iter().reduce(|mut error, other| {
+            error.combine(other);
+            error
+        })
+    }
 }
 
 impl Dom {
@@ -100,7 +133,16 @@ impl Dom {
         _state: Rc<RefCell<State>>,
         _append_nodes: bool,
     ) -> Self {
-        let mut dom = Self::default(_state, props);
+        Self::default(_state, props).generate_in(elements, details, _append_nodes)
+    }
+
+    fn generate_in(
+        self,
+        elements: &Vec<Element>,
+        details: &LocalDetails,
+        _append_nodes: bool,
+    ) -> Self {
+        let mut dom = self;
         if !_append_nodes {
             dom._append_nodes = false;
             dom.mount.insert(
@@ -109,14 +151,141 @@ impl Dom {
             );
         }
         dom.generate_elements("target", elements, details);
-        if _append_nodes {
-            dom.update
-                .push("self.state.borrow_mut().updated_idents.clear();".to_string());
-        }
         // _state.callbacks.append(&mut dom.binded_callbacks);
         dom
     }
 
+    pub fn generate_root(
+        elements: &Vec<Element>,
+        details: &LocalDetails,
+        props: HashMap<String, String>,
+        _state: Rc<RefCell<State>>,
+        _append_nodes: bool,
+    ) -> Self {
+        //! A child component is inserted before an anchor, like sub doms (`_append_nodes` false).
+        // The dom is known as the component's one while its elements are generated
+        let mut dom = Self::default(_state, props);
+        dom.root = true;
+        let mut dom = dom.generate_in(elements, details, _append_nodes);
+        dom.component_props = details
+            .props()
+            .map(|(state, prop)| ComponentProp {
+                ident: state.ident.to_string(),
+                ty: state.ty.to_token_stream().to_string(),
+                default: prop
+                    .default
+                    .as_ref()
+                    .map(|default| default.to_token_stream().to_string()),
+            })
+            .collect();
+        // Derived values are recomputed first : nodes reading them are then updated too
+        let recomputes: Vec<String> = details
+            .deriveds()
+            .filter(|(_, derived)| !derived.deps.is_empty())
+            .map(|(state, derived)| {
+                let (expr, _, _) = dom.rewrite_expr(&derived.expr, details, "self.");
+                format!(
+                    r#"if {} {{
+                        let value = {};
+                        let mut state = self.state.borrow_mut();
+                        state.{ident} = value;
+                        state.updated_idents.insert("{ident}".to_string());
+                    }}"#,
+                    idents_updated(&derived.deps),
+                    quote!(#expr),
+                    ident = state.ident,
+                )
+            })
+            .collect();
+        dom.update.splice(0..0, recomputes);
+        // Effects run after the first mount and once nodes are updated. The states they write
+        // are updated at the next tick : the component is marked dirty again. An effect doesn't
+        // depend on the states it writes, it would rerun itself endlessly.
+        let mut written_after_update = Vec::new();
+        for effect in &details.effects {
+            let (expr, _, _) = dom.rewrite_expr(&effect.expr, details, "self.");
+            let written = written_states(&effect.expr, details);
+            dom.mount_mounted
+                .push(format!("{}; {}", quote!(#expr), record_written(&written)));
+            let deps: HashSet<String> = effect
+                .deps
+                .iter()
+                .filter(|dep| !written.contains(dep))
+                .cloned()
+                .collect();
+            if !deps.is_empty() {
+                // `written_idents` is only declared when an effect writes a state
+                let record = if written.is_empty() {
+                    String::new()
+                } else {
+                    format!("written_idents.extend([{}]);", quoted(&written))
+                };
+                dom.update.push(format!(
+                    "if {} {{ {}; {record} }}",
+                    idents_updated(&deps),
+                    quote!(#expr),
+                ));
+                written_after_update.extend(written);
+            }
+        }
+        let hooks = |kind| {
+            details
+                .hooks(kind)
+                .map(|hook| {
+                    let (body, _, _) = dom.rewrite_expr(&hook.body, details, "self.");
+                    (
+                        format!("{{ {}; }}", quote!(#body)),
+                        written_states(&hook.body, details),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let on_mount = hooks(HookKind::OnMount);
+        let before_update = hooks(HookKind::BeforeUpdate);
+        let after_update = hooks(HookKind::AfterUpdate);
+        let on_destroy = hooks(HookKind::OnDestroy);
+        dom.mount_mounted.extend(
+            on_mount
+                .into_iter()
+                .map(|(body, written)| format!("{body} {}", record_written(&written))),
+        );
+        // Written before the nodes are updated, they are seen by this update
+        dom.update.splice(
+            0..0,
+            before_update.into_iter().map(|(body, written)| {
+                let inserts = written.iter().map(|ident| {
+                    format!(
+                        r#"self.state.borrow_mut().updated_idents.insert("{ident}".to_string());"#
+                    )
+                });
+                format!("{body} {}", inserts.collect::<String>())
+            }),
+        );
+        // Not recorded : running at each update, they would keep the component dirty
+        dom.update
+            .extend(after_update.into_iter().map(|(body, _)| body));
+        dom.update
+            .push("self.state.borrow_mut().updated_idents.clear();".to_string());
+        if !written_after_update.is_empty() {
+            dom.update.insert(
+                0,
+                "let mut written_idents: Vec<&str> = Vec::new();".to_string(),
+            );
+            dom.update.push(
+                r#"if !written_idents.is_empty() {
+                    let mut state = self.state.borrow_mut();
+                    state.updated_idents.extend(written_idents.into_iter().map(String::from));
+                    state.runtime.borrow_mut().dirty_ids.insert(self.component_id);
+                }"#
+                .to_string(),
+            );
+        }
+        // Before the nodes and listeners are removed
+        dom.drop
+            .splice(0..0, on_destroy.into_iter().map(|(body, _)| body));
+        dom
+    }
+
     fn add_event_listener(
         &mut self,
         element_name: &str,
@@ -149,12 +318,18 @@ impl Dom {
                 AttrExprType::String(text) => (text.to_string(), None),
                 AttrExprType::Ident(ref ident) => (format_ident!(self.props, ident), Some(ident)),
                 AttrExprType::Block(block) => {
-                    block.brace_token.span.warning(format!(
-                        "Attribute's Block is not handled yet. To implement it see `{}:{}`",
-                        file!(),
-                        line!()
-                    ));
-                    // Could use IdentModifier, to add .borrow() or borrow_mut()
+                    if namespace.is_empty() {
+                        let expr = block_to_expr(block);
+                        self.generate_reactive_attribute(element_name, name, &expr, details);
+                    } else if namespace == "on" {
+                        self.generate_inline_event(element_name, name, block, details);
+                    } else {
+                        block.brace_token.span.warning(format!(
+                            "Attribute's Block is not handled yet with a namespace. To implement it see `{}:{}`",
+                            file!(),
+                            line!()
+                        ));
+                    }
                     continue;
                 }
             };
@@ -219,14 +394,156 @@ impl Dom {
                     AttrExprType::String(_) => self.init.push(format!(
                         r#"{element_name}.set_attribute("{name}", "{init_value}")?;"#
                     )),
-                    _ => self.mount.push(format!(
-                        r#"self.{element_name}.set_attribute("{name}", "{init_value}")?;"#
-                    )),
+                    AttrExprType::Ident(ident) => self.generate_reactive_attribute(
+                        element_name,
+                        name,
+                        &syn::parse_quote!(#ident),
+                        details,
+                    ),
+                    // Handled before
+                    AttrExprType::Block(_) => (),
                 }
             }
         }
     }
 
+    fn generate_inline_event(
+        &mut self,
+        element_name: &str,
+        event_name: &str,
+        block: &Block,
+        details: &LocalDetails,
+    ) {
+        //! Inline handler compiled into an anonymous callback stored in the state.
+        let position_in_callbacks = self._state.borrow().callbacks.len();
+        let mut ident_modifier = details.get_ident_modifier("s");
+        let callback = ClosureTemplate::string_from_block(
+            format!("inline_callback_{position_in_callbacks}"),
+            block,
+            &mut ident_modifier,
+        );
+        self._state.borrow_mut().callbacks.push(callback);
+        self.add_event_listener(element_name, event_name, position_in_callbacks);
+    }
+
+    fn generate_component(
+        &mut self,
+        parent_name: &str,
+        component: &str,
+        attrs: &Vec<Attribute>,
+        details: &LocalDetails,
+    ) {
+        //! Child component (ex: `<Counter start={5} on:done={finish}/>`) generated by
+        //! `full! { Counter { ... } ... }`. It's registered in the runtime to be updated
+        //! on its own, its events are listened on its top level nodes.
+        let name = self.generate_node_name("component");
+        let anchor = self.create_empty_node(parent_name);
+        self.fields
+            .insert(name.clone(), format!("Rc<RefCell<{component}::DOM>>"));
+
+        let mut props = Vec::new();
+        for Attribute {
+            name: attr_name,
+            expr,
+            namespace,
+        } in attrs
+        {
+            if namespace == "on" {
+                let position_in_callbacks = match expr {
+                    AttrExprType::Block(block) => {
+                        let position_in_callbacks = self._state.borrow().callbacks.len();
+                        let mut ident_modifier = details.get_ident_modifier("s");
+                        let callback = ClosureTemplate::string_from_block(
+                            format!("inline_callback_{position_in_callbacks}"),
+                            block,
+                            &mut ident_modifier,
+                        );
+                        self._state.borrow_mut().callbacks.push(callback);
+                        position_in_callbacks
+                    }
+                    // Error should have been handled in crate::check, so it won't panic
+                    AttrExprType::Ident(ident) => details
+                        .events_closures
+                        .iter()
+                        .position(|event_closure| event_closure.ident == *ident)
+                        .unwrap(),
+                    AttrExprType::String(_) => continue,
+                };
+                self.mount_mounted.push(format!(
+                    r#"self.{name}.borrow().listen("{attr_name}", &self.state.borrow().callbacks[{position_in_callbacks}])?;"#
+                ));
+                continue;
+            }
+            let expr = match expr {
+                AttrExprType::String(text) => {
+                    props.push(format!(".{attr_name}({text:?}.into())"));
+                    continue;
+                }
+                AttrExprType::Ident(ident) => syn::parse_quote!(#ident),
+                AttrExprType::Block(block) => block_to_expr(block),
+            };
+            // A variable is cloned, not moved out of the state
+            let expr: Expr = match expr {
+                Expr::Path(_) => syn::parse_quote!(Clone::clone(&#expr)),
+                expr => expr,
+            };
+            let (value, _, _) = self.rewrite_expr(&expr, details, "");
+            props.push(format!(".{attr_name}({})", quote!(#value)));
+
+            // New values are given to the child which is then updated by the runtime
+            let (value, used_state_idents, use_props) = self.rewrite_expr(&expr, details, "self.");
+            self.update.push(format!(
+                "if {} {{ self.{name}.borrow().set_props({component}::Props::builder().{attr_name}({})); }}",
+                update_needed(&used_state_idents, use_props),
+                quote!(#value)
+            ));
+        }
+
+        // Contexts are found from the owning component, `id` is a row's index inside a loop
+        self.init.push(format!(
+            "let {name} = Rc::new(RefCell::new({component}::DOM::with_props(
+                state.borrow().runtime.clone(),
+                Some(component_id),
+                {component}::Props::builder(){}.build(),
+            )?));
+            state.borrow().runtime.borrow_mut().components.insert({name}.borrow().id, {name}.clone());",
+            props.join("")
+        ));
+        self.mount.push(format!(
+            "self.{name}.borrow_mut().mount(self.{anchor}.unchecked_ref())?;"
+        ));
+        if parent_name == "target" {
+            self.move_nodes.push(format!(
+                "self.{name}.borrow().move_before(self.{anchor}.unchecked_ref())?;"
+            ));
+        }
+        // Nodes are removed once the runtime doesn't own the component anymore
+        self.drop.push(format!(
+            "self.state.borrow().runtime.borrow_mut().components.remove(&self.{name}.borrow().id);"
+        ));
+    }
+
+    fn generate_reactive_attribute(
+        &mut self,
+        element_name: &str,
+        name: &str,
+        expr: &Expr,
+        details: &LocalDetails,
+    ) {
+        //! Attribute computed from state, recomputed only when the idents it reads are updated.
+        let (init_expr, _, _) = self.rewrite_expr(expr, details, "");
+        let (update_expr, used_state_idents, use_props) = self.rewrite_expr(expr, details, "self.");
+        self.init.push(format!(
+            r#"set_attribute_value(&{element_name}, "{name}", &({}))?;"#,
+            quote!(#init_expr)
+        ));
+        let recheck = update_needed(&used_state_idents, use_props);
+        self.update.push(format!(
+            r#"if {recheck} {{ set_attribute_value(&self.{element_name}, "{name}", &({}))?; }}"#,
+            quote!(#update_expr)
+        ));
+    }
+
     fn generate_node_name(&mut self, name: &str) -> String {
         let count = self._tag_count.entry(name.to_string()).or_insert(0);
         *count += 1;
@@ -242,6 +559,12 @@ impl Dom {
                 line = format!("parent.insert_before(&self.{}, Some(target))?;", node_name);
             }
             self.drop.push(format!("self.{node_name}.remove();"));
+            self.move_nodes.push(format!(
+                "parent.insert_before(&self.{node_name}, Some(target))?;"
+            ));
+            self.listen_nodes.push(format!(
+                "self.{node_name}.add_event_listener_with_callback(event_type, callback.as_ref().unchecked_ref())?;"
+            ));
         } else {
             line = format!("self.{}.append_child(&self.{})?;", target, node_name);
         }
@@ -310,6 +633,14 @@ impl Dom {
         for el in elements {
             // let mut name: String = "".to_string();
             match el {
+                // Its children are refused by the parser, slots don't exist yet
+                Element::Classic(Classic {
+                    name: element_name,
+                    ref attrs,
+                    ..
+                }) if element_name.starts_with(char::is_uppercase) => {
+                    self.generate_component(parent_name, element_name, attrs, details);
+                }
                 Element::Classic(Classic {
                     name: element_name,
                     ref attrs,
@@ -350,19 +681,34 @@ impl Dom {
                         self.update.push(update);
                     }
                     ExprElement::If { conditions } => {
-                        for Condition { expr, children } in conditions {
-                            // TODO create sub dom element to handle the condition.
-                            todo!(
-                                "Sub dom nodes (init / update / drop) : {:?} / {:?}",
-                                expr,
-                                children
-                            )
-                        }
+                        self.generate_if(parent_name, details, conditions)
+                    }
+                    ExprElement::Match { expr, cases } => {
+                        self.generate_match(parent_name, details, expr, cases)
                     }
-                    ExprElement::For { expr, children } => {
-                        self.generate_for_loop(parent_name, details, &expr, children)
+                    ExprElement::Await {
+                        future,
+                        pending,
+                        then,
+                        catch,
+                    } => self.generate_await(parent_name, details, future, pending, then, catch),
+                    ExprElement::Block(block) => {
+                        self.generate_text_block(parent_name, details, block)
                     }
-                    _ => continue,
+                    ExprElement::Lit(lit) => {
+                        self.create_text_node(
+                            parent_name,
+                            "text",
+                            "create_text_node",
+                            &lit.value(),
+                            true,
+                        );
+                    }
+                    ExprElement::For {
+                        expr,
+                        key,
+                        children,
+                    } => self.generate_for_loop(parent_name, details, &expr, key, children),
                 },
                 Element::Comment(comment) => {
                     self.create_text_node(parent_name, "text", "create_comment", comment, true);
@@ -373,252 +719,1411 @@ impl Dom {
         vec!["".to_string()]
     }
 
+    fn rewrite_expr(
+        &self,
+        expr: &syn::Expr,
+        details: &LocalDetails,
+        prefix: &str,
+    ) -> (syn::Expr, HashSet<String>, bool) {
+        //! Rewrite an expression to be used in `init` (prefix "") or `mount`/`update` ("self.").
+        //! Return the rewritten expression, the state idents it reads and whether it reads
+        //! props or stores (not tracked by `updated_idents`).
+        let mut expr = expr.clone();
+        // Props first : loop idents shadow state's ones
+        let mut props_modifier = PropsModifier::new(
+            self.props.keys().cloned().collect(),
+            format!("{prefix}props"),
+        );
+        props_modifier.visit_expr_mut(&mut expr);
+        // A written state is borrowed once for the whole expression, like in handlers : a
+        // borrow for each access would overlap the mutable one (ex: `point.x = step`)
+        let guard = (!written_states(&expr, details).is_empty()).then(|| "state".to_string());
+        let mut store_modifier = StoreModifier::new(format!("{prefix}state"));
+        store_modifier.guard = guard.clone();
+        store_modifier.visit_expr_mut(&mut expr);
+        let mut ident_modifier = details.get_ident_modifier(&format!("{prefix}state"));
+        ident_modifier.guard = guard.clone();
+        ident_modifier.visit_expr_mut(&mut expr);
+        if guard.is_some() {
+            let state: Expr = syn::parse_str(&format!("{prefix}state")).unwrap();
+            expr = syn::parse_quote!({
+                let mut guard = #state.borrow_mut();
+                #[allow(unused_variables)]
+                let state = &mut *guard;
+                #expr
+            });
+        }
+        let use_stores = !store_modifier.used.is_empty();
+        self._state.borrow_mut().stores.extend(store_modifier.used);
+        (
+            expr,
+            &ident_modifier.names_ref | &ident_modifier.names_refmut,
+            !props_modifier.used.is_empty() || use_stores,
+        )
+    }
+
+    fn generate_text_block(&mut self, parent_name: &str, details: &LocalDetails, block: &Block) {
+        //! Text node displaying the block, updated when the idents it reads are updated.
+        let block = block_to_expr(block);
+        let (init_expr, _, _) = self.rewrite_expr(&block, details, "");
+        let (update_expr, used_state_idents, use_props) =
+            self.rewrite_expr(&block, details, "self.");
+        let name = self.create_text_node(
+            parent_name,
+            "text",
+            "create_text_node",
+            &format!(r#"&format!("{{}}", {})"#, quote!(#init_expr)),
+            false,
+        );
+        let recheck = update_needed(&used_state_idents, use_props);
+        self.update.push(format!(
+            r#"if {recheck} {{ self.{name}.set_text_content(Some(&format!("{{}}", {}))); }}"#,
+            quote!(#update_expr)
+        ));
+    }
+
+    fn sub_dom_props(&self, mod_name: &str, bindings: &[Ident]) -> (String, String) {
+        //! Forward current props and bindings to a sub dom : (init of `Props`, update of `dom.props`).
+        let init_props = self
+            .props
+            .keys()
+            .filter(|ident| !bindings.iter().any(|binding| binding == ident))
+            .map(|ident| format!("{ident}: self.props.{ident}.clone(),"))
+            .chain(
+                bindings
+                    .iter()
+                    .map(|binding| format!("{binding}: {binding}.clone(),")),
+            )
+            .collect::<String>();
+        let update_props = self
+            .props
+            .keys()
+            .map(|ident| format!("dom.props.{ident} = self.props.{ident}.clone();"))
+            .collect::<String>();
+        (
+            format!("{mod_name}::Props {{ {init_props} }}"),
+            update_props,
+        )
+    }
+
+    fn generate_if(&mut self, parent_name: &str, details: &LocalDetails, conditions: &[Condition]) {
+        //! Each condition's children are a sub dom, the mounted one is inserted before an anchor.
+        let name = self.generate_node_name("if_block");
+        let branch_name = format!("branch_{name}");
+        self.fields
+            .insert(branch_name.clone(), "Option<usize>".to_string());
+        self.init.push(format!("let {branch_name} = None;"));
+        let anchor = self.create_empty_node(parent_name);
+
+        let mut used_state_idents = HashSet::new();
+        let mut use_props = false;
+        let mut select_branch = Vec::new();
+        let mut create_branch = Vec::new();
+        let mut update_branch = Vec::new();
+        let mut clear_branches = Vec::new();
+        for (i, Condition { expr, children }) in conditions.iter().enumerate() {
+            let (expr, idents, expr_use_props) = self.rewrite_expr(expr, details, "self.");
+            used_state_idents.extend(idents);
+            use_props |= expr_use_props;
+
+            let mod_name = format!("{name}_{i}");
+            let dom_name = format!("dom_{mod_name}");
+            let (init_props, update_props) = self.sub_dom_props(&mod_name, &[]);
+            self.fields
+                .insert(dom_name.clone(), format!("Option<{mod_name}::DOM>"));
+            self.init.push(format!("let {dom_name} = None;"));
+
+            select_branch.push(format!("if {} {{ Some({i}) }}", quote!(#expr)));
+            create_branch.push(format!(
+                "Some({i}) => {{
+                    let mut dom = {mod_name}::DOM::from_state(self.state.clone(), self.component_id, self.id, {init_props})?;
+                    dom.mount(self.{anchor}.unchecked_ref())?;
+                    self.{dom_name} = Some(dom);
+                }}"
+            ));
+            update_branch.push(format!(
+                "if let Some(dom) = self.{dom_name}.as_mut() {{ {update_props} dom.update()?; }}"
+            ));
+            clear_branches.push(format!("self.{dom_name} = None;"));
+            if parent_name == "target" {
+                self.move_nodes.push(format!(
+                    "if let Some(dom) = self.{dom_name}.as_ref() {{ dom.move_before(self.{anchor}.unchecked_ref())?; }}"
+                ));
+            }
+
+            let sub_dom = Dom::generate(
+                children,
+                details,
+                self.props.clone(),
+                self._state.clone(),
+                false,
+            );
+            self.sub_doms.insert(mod_name, sub_dom);
+        }
+
+        let select_branch = format!("{} else {{ None }}", select_branch.join(" else "));
+        let swap_branch = format!(
+            "{} self.{branch_name} = branch; match branch {{ {} _ => (), }}",
+            clear_branches.join(" "),
+            create_branch.join(" "),
+        );
+        self.mount
+            .push(format!("let branch = {select_branch}; {swap_branch}"));
+
+        let recheck = update_needed(&used_state_idents, use_props);
+        self.update.push(format!(
+            "let branch = if {recheck} {{ {select_branch} }} else {{ self.{branch_name} }};
+            if branch != self.{branch_name} {{ {swap_branch} }} else {{ {} }}",
+            update_branch.join(" "),
+        ));
+    }
+
+    fn generate_match(
+        &mut self,
+        parent_name: &str,
+        details: &LocalDetails,
+        expr: &Expr,
+        cases: &[Case],
+    ) {
+        //! Each case's children are a sub dom, the pattern's bindings are given as props.
+        let name = self.generate_node_name("match_block");
+        let branch_name = format!("branch_{name}");
+        self.fields
+            .insert(branch_name.clone(), "Option<usize>".to_string());
+        self.init.push(format!("let {branch_name} = None;"));
+        let anchor = self.create_empty_node(parent_name);
+
+        // Patterns of types the file doesn't define are typed by rustc
+        let type_info = match match_bindings(details, expr, cases).map_or_else(
+            || query_bindings(details, match_query(details, expr, cases), expr.span()),
+            Ok,
+        ) {
+            Ok(type_info) => type_info,
+            Err(error) => {
+                self._errors.push(syn::Error::new(
+                    error.span(),
+                    format!("Can't find the types of the cases' bindings.\n{error}"),
+                ));
+                return;
+            }
+        };
+
+        let (expr, mut used_state_idents, mut use_props) =
+            self.rewrite_expr(expr, details, "self.");
+        let mut variants = Vec::new();
+        let mut select_arms = Vec::new();
+        let mut branch_arms = Vec::new();
+        let mut create_arms = Vec::new();
+        let mut update_arms = Vec::new();
+        let mut idle_updates = Vec::new();
+        let mut clear_branches = Vec::new();
+        for (
+            i,
+            Case {
+                pat,
+                guard,
+                children,
+            },
+        ) in cases.iter().enumerate()
+        {
+            let mod_name = format!("{name}_{i}");
+            let dom_name = format!("dom_{mod_name}");
+            self.fields
+                .insert(dom_name.clone(), format!("Option<{mod_name}::DOM>"));
+            self.init.push(format!("let {dom_name} = None;"));
+
+            let mut extractor = BindingExtractor::new();
+            extractor.visit_pat(pat);
+            let bindings: Vec<Ident> = extractor
+                .idents
+                .into_iter()
+                .filter(|ident| type_info.iter().any(|binding| ident == &binding.name))
+                .collect();
+            let mut props = self.props.clone();
+            props.extend(
+                type_info
+                    .iter()
+                    .filter(|binding| bindings.iter().any(|ident| ident == &binding.name))
+                    .map(|binding| (binding.name.clone(), binding.ty.clone())),
+            );
+            let (init_props, _) = self.sub_dom_props(&mod_name, &bindings);
+
+            let guard = match guard {
+                Some(guard) => {
+                    let (guard, idents, guard_use_props) =
+                        self.rewrite_expr(guard, details, "self.");
+                    used_state_idents.extend(idents);
+                    use_props |= guard_use_props;
+                    format!("if {}", quote!(#guard))
+                }
+                None => String::new(),
+            };
+            variants.push(format!("Case{i}({mod_name}::Props),"));
+            select_arms.push(format!(
+                "{} {guard} => Selected::Case{i}({init_props}),",
+                quote!(#pat)
+            ));
+            branch_arms.push(format!("Selected::Case{i}(_) => Some({i}),"));
+            create_arms.push(format!(
+                "Selected::Case{i}(props) => {{
+                    let mut dom = {mod_name}::DOM::from_state(self.state.clone(), self.component_id, self.id, props)?;
+                    dom.mount(self.{anchor}.unchecked_ref())?;
+                    self.{dom_name} = Some(dom);
+                }}"
+            ));
+            update_arms.push(format!(
+                "Selected::Case{i}(props) => if let Some(dom) = self.{dom_name}.as_mut() {{
+                    dom.props = props;
+                    dom.update()?;
+                }}"
+            ));
+            idle_updates.push(format!(
+                "if let Some(dom) = self.{dom_name}.as_mut() {{ dom.update()?; }}"
+            ));
+            clear_branches.push(format!("self.{dom_name} = None;"));
+            if parent_name == "target" {
+                self.move_nodes.push(format!(
+                    "if let Some(dom) = self.{dom_name}.as_ref() {{ dom.move_before(self.{anchor}.unchecked_ref())?; }}"
+                ));
+            }
+
+            let sub_dom = Dom::generate(children, details, props, self._state.clone(), false);
+            self.sub_doms.insert(mod_name, sub_dom);
+        }
+
+        // Props are built while the state is borrowed, sub doms are created once it's released
+        let select = format!(
+            "enum Selected {{ {} None }}
+            #[allow(unreachable_patterns)]
+            let selected = match ({}).clone() {{ {} _ => Selected::None, }};
+            let branch = match &selected {{ {} Selected::None => None, }};",
+            variants.join(" "),
+            quote!(#expr),
+            select_arms.join(" "),
+            branch_arms.join(" "),
+        );
+        let swap_branch = format!(
+            "{} self.{branch_name} = branch; match selected {{ {} Selected::None => (), }}",
+            clear_branches.join(" "),
+            create_arms.join(" "),
+        );
+        self.mount.push(format!("{{ {select} {swap_branch} }}"));
+
+        let recheck = update_needed(&used_state_idents, use_props);
+        self.update.push(format!(
+            "if {recheck} {{
+                {select}
+                if branch != self.{branch_name} {{ {swap_branch} }}
+                else {{ match selected {{ {} Selected::None => (), }} }}
+            }} else {{ {} }}",
+            update_arms.join(" "),
+            idle_updates.join(" "),
+        ));
+    }
+
+    fn settled_props(
+        &mut self,
+        details: &LocalDetails,
+        future: &Expr,
+        settled: &Option<Settled>,
+        variant: Option<&str>,
+    ) -> (Vec<Ident>, HashMap<String, String>) {
+        //! Bindings of a then / catch pattern and the props of its sub dom.
+        let mut props = self.props.clone();
+        let pat = match settled {
+            Some(Settled { pat, .. }) => pat,
+            None => return (Vec::new(), props),
+        };
+        // Outputs of futures the file doesn't define are typed by rustc
+        let type_info = match settled_bindings(details, future, pat, variant).map_or_else(
+            || {
+                query_bindings(
+                    details,
+                    settled_query(details, future, pat, variant),
+                    pat.span(),
+                )
+            },
+            Ok,
+        ) {
+            Ok(type_info) => type_info,
+            Err(error) => {
+                self._errors.push(syn::Error::new(
+                    error.span(),
+                    format!("Can't find the types of this pattern's bindings.\n{error}"),
+                ));
+                return (Vec::new(), props);
+            }
+        };
+        let mut extractor = BindingExtractor::new();
+        extractor.visit_pat(pat);
+        let bindings: Vec<Ident> = extractor
+            .idents
+            .into_iter()
+            .filter_map(|ident| {
+                // The pattern follows the state's block, its bindings are the last ones
+                let binding = type_info
+                    .iter()
+                    .rev()
+                    .find(|binding| ident == &binding.name)?;
+                props.insert(binding.name.clone(), binding.ty.clone());
+                Some(ident)
+            })
+            .collect();
+        (bindings, props)
+    }
+
+    fn generate_await(
+        &mut self,
+        parent_name: &str,
+        details: &LocalDetails,
+        future: &Expr,
+        pending: &Vec<Element>,
+        then: &Option<Settled>,
+        catch: &Option<Settled>,
+    ) {
+        //! The future is spawned at mount, its output is stored in `settled_*` then the
+        //! component is marked dirty : the next update swaps the pending sub dom for the
+        //! then (or catch) one. The future is spawned again when its idents are updated.
+        let name = self.generate_node_name("await_block");
+        let anchor = self.create_empty_node(parent_name);
+
+        let mods = ["pending", "then", "catch"].map(|part| format!("{name}_{part}"));
+        let [pending_mod, then_mod, catch_mod] = &mods;
+        let settled_name = format!("settled_{name}");
+        // A component's dom has no props to forward, its `Props` are the state's
+        let (parent_props_type, parent_props) = match self.root {
+            true => ("()", "&()"),
+            false => ("Props", "&self.props"),
+        };
+        self.fields.insert(
+            settled_name.clone(),
+            format!("Rc<RefCell<Option<Box<dyn FnOnce(&{parent_props_type}) -> Result<{then_mod}::Props, {catch_mod}::Props>>>>>"),
+        );
+        self.init
+            .push(format!("let {settled_name} = Rc::new(RefCell::new(None));"));
+        for mod_name in &mods {
+            self.fields.insert(
+                format!("dom_{mod_name}"),
+                format!("Option<{mod_name}::DOM>"),
+            );
+            self.init.push(format!("let dom_{mod_name} = None;"));
+            if parent_name == "target" {
+                self.move_nodes.push(format!(
+                    "if let Some(dom) = self.dom_{mod_name}.as_ref() {{ dom.move_before(self.{anchor}.unchecked_ref())?; }}"
+                ));
+            }
+        }
+
+        let (then_bindings, then_props) =
+            self.settled_props(details, future, then, catch.as_ref().map(|_| "Ok"));
+        let (catch_bindings, catch_props) = self.settled_props(details, future, catch, Some("Err"));
+
+        // Inside the settle closure, props come from its argument and bindings are moved
+        let closure_props = |mod_name: &str, bindings: &[Ident]| {
+            let fields = self
+                .props
+                .keys()
+                .filter(|ident| !bindings.iter().any(|binding| binding == ident))
+                .map(|ident| format!("{ident}: parent_props.{ident}.clone(),"))
+                .chain(bindings.iter().map(|binding| format!("{binding},")))
+                .collect::<String>();
+            format!("{mod_name}::Props {{ {fields} }}")
+        };
+        let then_init = closure_props(then_mod, &then_bindings);
+        let catch_init = closure_props(catch_mod, &catch_bindings);
+        let then_pat = match then {
+            Some(Settled { pat, .. }) => quote!(#pat),
+            None => quote!(_),
+        };
+        let settle = match catch {
+            Some(Settled { pat, .. }) => format!(
+                "match output {{ Ok({then_pat}) => Ok({then_init}), Err({}) => Err({catch_init}), }}",
+                quote!(#pat)
+            ),
+            None => format!("{{ let {then_pat} = output; Ok({then_init}) }}"),
+        };
+
+        let (future, used_state_idents, _) = self.rewrite_expr(future, details, "self.");
+        let (pending_props, _) = self.sub_dom_props(pending_mod, &[]);
+        let clear_doms = mods
+            .iter()
+            .map(|mod_name| format!("self.dom_{mod_name} = None;"))
+            .collect::<String>();
+        let spawn = format!(
+            "{{
+                let settled = Rc::new(RefCell::new(None));
+                self.{settled_name} = settled.clone();
+                let future = {};
+                let runtime = self.state.borrow().runtime.clone();
+                let id = self.component_id;
+                wasm_bindgen_futures::spawn_local(async move {{
+                    let output = future.await;
+                    #[allow(unused_variables)]
+                    let settle: Box<dyn FnOnce(&{parent_props_type}) -> Result<{then_mod}::Props, {catch_mod}::Props>> =
+                        Box::new(move |parent_props: &{parent_props_type}| {settle});
+                    *settled.borrow_mut() = Some(settle);
+                    if !runtime.borrow().dirty_ids.contains(&id) {{
+                        runtime.borrow_mut().dirty_ids.insert(id);
+                    }}
+                }});
+                {clear_doms}
+                let mut dom = {pending_mod}::DOM::from_state(self.state.clone(), self.component_id, self.id, {pending_props})?;
+                dom.mount(self.{anchor}.unchecked_ref())?;
+                self.dom_{pending_mod} = Some(dom);
+            }}",
+            quote!(#future),
+        );
+        self.mount.push(spawn.clone());
+
+        // Parent's props are forwarded to the mounted sub dom, bindings are kept
+        let update_doms = mods
+            .iter()
+            .map(|mod_name| {
+                let (_, update_props) = self.sub_dom_props(mod_name, &[]);
+                format!(
+                    "if let Some(dom) = self.dom_{mod_name}.as_mut() {{ {update_props} dom.update()?; }}"
+                )
+            })
+            .collect::<String>();
+        // Props are not tracked, only updated idents spawn the future again
+        self.update.push(format!(
+            "if {} {{ {spawn} }} else {{
+                let settle = self.{settled_name}.borrow_mut().take();
+                match settle.map(|settle| settle({parent_props})) {{
+                    Some(Ok(props)) => {{
+                        {clear_doms}
+                        let mut dom = {then_mod}::DOM::from_state(self.state.clone(), self.component_id, self.id, props)?;
+                        dom.mount(self.{anchor}.unchecked_ref())?;
+                        self.dom_{then_mod} = Some(dom);
+                    }}
+                    Some(Err(props)) => {{
+                        {clear_doms}
+                        let mut dom = {catch_mod}::DOM::from_state(self.state.clone(), self.component_id, self.id, props)?;
+                        dom.mount(self.{anchor}.unchecked_ref())?;
+                        self.dom_{catch_mod} = Some(dom);
+                    }}
+                    None => {{ {update_doms} }}
+                }}
+            }}",
+            idents_updated(&used_state_idents),
+        ));
+
+        let empty = Vec::new();
+        let sub_doms = [
+            (pending_mod, pending, self.props.clone()),
+            (
+                then_mod,
+                then.as_ref().map_or(&empty, |then| &then.children),
+                then_props,
+            ),
+            (
+                catch_mod,
+                catch.as_ref().map_or(&empty, |catch| &catch.children),
+                catch_props,
+            ),
+        ];
+        for (mod_name, children, props) in sub_doms {
+            let sub_dom = Dom::generate(children, details, props, self._state.clone(), false);
+            self.sub_doms.insert(mod_name.clone(), sub_dom);
+        }
+    }
+
     fn generate_for_loop(
         &mut self,
         parent_name: &str,
         details: &LocalDetails,
         expr: &ExprForLoop,
+        key: &Option<Expr>,
         children: &Vec<Element>,
     ) {
         // TODO create sub dom element to handle the loop.
         let name = self.generate_node_name("for_loop");
 
-        match expr.pat {
-            syn::Pat::Ident(syn::PatIdent {
-                ref ident,
-                mutability,
-                ..
-            }) => {
-                println!("{} ; {}", ident, mutability.is_some());
-                // let mut for_loop_init = expr.clone();
-                let mut for_loop_mount = expr.clone();
-                // let mut for_loop_update = expr.clone();
-                let for_loop_with_state = expr.clone();
-                let mut ident_modifier = details.get_ident_modifier("self.state");
-                ident_modifier.visit_expr_mut(&mut *for_loop_mount.expr);
-                let type_info = thir::get_pat_bindings(
-                    format!(
-                        "{}\n{}",
-                        details.block,
-                        quote!(#for_loop_with_state).to_string(),
-                    ),
-                    // expr.for_token.span.source_file().path().file_name(),
-                    "unknown_file_name".into(),
-                    name.clone(),
-                );
-                println!("INFO {:#?}", type_info);
+        // let mut for_loop_init = expr.clone();
+        let mut for_loop_mount = expr.clone();
+        // let mut for_loop_update = expr.clone();
+        let mut ident_modifier = details.get_ident_modifier("self.state");
+        ident_modifier.visit_expr_mut(&mut *for_loop_mount.expr);
+        // Items are typed from the states' types, the props are their clones
+        let type_info = match for_item_bindings(details, expr) {
+            Ok(bindings) => bindings,
+            Err(error) => {
+                self._errors.push(error);
+                return;
+            }
+        };
 
-                let updated_name = ident_from(format!("updated_{name}"));
-                let dom_name = ident_from(format!("dom_{name}"));
-                let mod_name = ident_from(name.clone());
+        let updated_name = ident_from(format!("updated_{name}"));
+        let dom_name = ident_from(format!("dom_{name}"));
+        let mod_name = ident_from(name.clone());
 
-                self.fields
-                    .insert(dom_name.to_string(), format!("Vec<{mod_name}::DOM>"));
-                self.init.push(format!("let {dom_name} = Vec::new();"));
-                self.fields
-                    .insert(updated_name.to_string(), "bool".to_string());
-                self.init.push(format!("let {updated_name} = false;"));
-                let empty_after = ident_from(self.create_empty_node(parent_name));
+        let dom_type = match key {
+            Some(_) => format!("Vec<Keyed<{mod_name}::DOM>>"),
+            None => format!("Vec<{mod_name}::DOM>"),
+        };
+        self.fields.insert(dom_name.to_string(), dom_type);
+        self.init.push(format!("let {dom_name} = Vec::new();"));
+        self.fields
+            .insert(updated_name.to_string(), "bool".to_string());
+        self.init.push(format!("let {updated_name} = false;"));
+        let empty_after = ident_from(self.create_empty_node(parent_name));
+        if parent_name == "target" {
+            self.move_nodes.push(format!(
+                "for dom in self.{dom_name}.iter() {{ dom.move_before(self.{empty_after}.unchecked_ref())?; }}"
+            ));
+        }
 
-                let used_state_idents: syn::ExprArray = syn::parse_str(
-                    format!(
-                        "[{}]",
-                        (&ident_modifier.names_ref | &ident_modifier.names_refmut)
-                            .iter()
-                            .map(|ident| format!(r#""{}".to_string()"#, ident))
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )
-                    .as_str(),
-                )
-                .unwrap();
-                // self.update.push(
-                //     quote!(
-                //         console::log_1(&format!("UPDATE IDENTS {:?}", self.state.borrow().updated_idents).into());
-                //         if self.state.borrow().updated_idents.intersection(&HashSet::from(#used_state_idents)).count() > 0  {
-                //             console::log_1(&"Update loop".into());
-                //             self.#dom_name.clear();
-                //             let mut i = 0;
-                //             #for_loop_mount
-                //         }
-                //     )
-                //     .to_string(),
-                // );
-                // self.update.push(
-                //     quote!(
-                //     for dom in self.#dom_name.iter_mut() {
-                //         dom.update()?;
-                //     }
-                //     )
-                //     .to_string(),
-                // );
-                let mut extractor = IdentExtractor::new();
-
-                let mut strings: Vec<String> = Vec::new();
-                strings.push("plop".to_string());
-                for s in strings {
-                    println!("{}", s);
-                }
-                for (i, plop) in (0..5u32).enumerate() {
-                    let p = i + plop as usize;
-                    println!("{}: {} // {}", i, plop, p);
-                }
+        let used_state_idents: syn::ExprArray = syn::parse_str(
+            format!(
+                "[{}]",
+                (&ident_modifier.names_ref | &ident_modifier.names_refmut)
+                    .iter()
+                    .map(|ident| format!(r#""{}".to_string()"#, ident))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .as_str(),
+        )
+        .unwrap();
+        // self.update.push(
+        //     quote!(
+        //         console::log_1(&format!("UPDATE IDENTS {:?}", self.state.borrow().updated_idents).into());
+        //         if self.state.borrow().updated_idents.intersection(&HashSet::from(#used_state_idents)).count() > 0  {
+        //             console::log_1(&"Update loop".into());
+        //             self.#dom_name.clear();
+        //             let mut i = 0;
+        //             #for_loop_mount
+        //         }
+        //     )
+        //     .to_string(),
+        // );
+        // self.update.push(
+        //     quote!(
+        //     for dom in self.#dom_name.iter_mut() {
+        //         dom.update()?;
+        //     }
+        //     )
+        //     .to_string(),
+        // );
+        let loop_idents: Vec<Ident> = type_info.iter().map(|(ident, _)| ident.clone()).collect();
 
-                extractor.visit_pat(&expr.pat);
+        let props = type_info
+            .iter()
+            .map(|(ident, ty)| (ident.to_string(), quote!(#ty).to_string()))
+            .collect::<HashMap<_, _>>();
 
-                let loop_idents: Vec<syn::FieldValue> = extractor
-                    .idents
-                    .iter()
-                    .map(|ident| syn::parse_quote!(#ident))
-                    .collect();
+        let update_props: Vec<syn::Stmt> = loop_idents
+            .iter()
+            .map(|ident| syn::parse_quote!(dom.props.#ident = #ident.clone();))
+            // .map(|ident| {
+            //     let type_ = props.get(&ident.to_string());
+            //     match type_ {
+            //         Some(type_) => {
+            //             if type_.starts_with('&') {
+            //                 syn::parse_quote!(dom.props.#ident = #ident.clone();)
+            //             } else {
+            //                 syn::parse_quote!(dom.props.#ident = #ident;)
+            //             }
+            //         },
+            //         None => {
+            //             for_loop_mount.for_token.span.error("Can't coerce type from for loop.");
+            //             panic!("Can't coerce type from for loop.");
+            //         }
+            //     }
+            // })
+            .collect();
 
-                let loop_idents_name: Vec<String> = extractor
-                    .idents
-                    .iter()
-                    .map(|ident| ident.to_string())
-                    .collect();
+        let init_props: Vec<syn::FieldValue> = loop_idents
+            .iter()
+            .map(|ident| syn::parse_quote!(#ident: #ident.clone()))
+            // .map(|ident| {
+            //     let type_ = props.get(&ident.to_string());
+            //     match type_ {
+            //         Some(t) => {
+            //             if t.starts_with('&') {
+            //                 syn::parse_quote!(#ident)
+            //             } else {
+            //                 syn::parse_quote!(#ident: #ident.clone())
+            //             }
+            //         }
+            //         None => {
+            //             for_loop_mount
+            //                 .for_token
+            //                 .span
+            //                 .error("Can't coerce type from for loop.");
+            //             panic!("Can't coerce type from for loop.");
+            //         }
+            //     }
+            // })
+            .collect();
 
-                let props = type_info
-                    .iter()
-                    .filter(|binding| loop_idents_name.contains(&binding.name))
-                    .map(|binding| (binding.name.clone(), binding.ty.clone()))
-                    .collect::<HashMap<_, _>>();
+        for_loop_mount.body = syn::parse_quote!(
+        {
+            dom_index += 1;
+            let mut dom_instance = #mod_name::DOM::from_state(self.state.clone(), self.component_id, dom_index, #mod_name::Props{ #(#init_props,)* })?;
+            dom_instance.mount(self.#empty_after.unchecked_ref())?;
+            self.#dom_name.push(dom_instance);
+        }
+        );
 
-                let update_props: Vec<syn::Stmt> = extractor
-                    .idents
-                    .iter()
-                    .map(|ident| syn::parse_quote!(dom.props.#ident = #ident.clone();))
-                    // .map(|ident| {
-                    //     let type_ = props.get(&ident.to_string());
-                    //     match type_ {
-                    //         Some(type_) => {
-                    //             if type_.starts_with('&') {
-                    //                 syn::parse_quote!(dom.props.#ident = #ident.clone();)
-                    //             } else {
-                    //                 syn::parse_quote!(dom.props.#ident = #ident;)
-                    //             }
-                    //         },
-                    //         None => {
-                    //             for_loop_mount.for_token.span.error("Can't coerce type from for loop.");
-                    //             panic!("Can't coerce type from for loop.");
-                    //         }
-                    //     }
-                    // })
-                    .collect();
-
-                let init_props: Vec<syn::FieldValue> = extractor
-                    .idents
-                    .iter()
-                    .map(|ident| syn::parse_quote!(#ident: #ident.clone()))
-                    // .map(|ident| {
-                    //     let type_ = props.get(&ident.to_string());
-                    //     match type_ {
-                    //         Some(t) => {
-                    //             if t.starts_with('&') {
-                    //                 syn::parse_quote!(#ident)
-                    //             } else {
-                    //                 syn::parse_quote!(#ident: #ident.clone())
-                    //             }
-                    //         }
-                    //         None => {
-                    //             for_loop_mount
-                    //                 .for_token
-                    //                 .span
-                    //                 .error("Can't coerce type from for loop.");
-                    //             panic!("Can't coerce type from for loop.");
-                    //         }
-                    //     }
-                    // })
-                    .collect();
-
-                for_loop_mount.body = syn::parse_quote!(
-                {
-                    i += 1;
-                    let mut dom_instance = #mod_name::DOM::from_state(self.state.clone(), i, #mod_name::Props{ #(#init_props,)* })?;
-                    dom_instance.mount(self.#empty_after.unchecked_ref())?;
-                    self.#dom_name.push(dom_instance);
-                }
-                );
+        let for_expr = &for_loop_mount.expr;
+        let pat = &expr.pat;
+        if let Some(key) = key {
+            // Sub doms are reused by key, only the ones out of place are moved
+            let reconcile = quote!(
+                #[allow(unused_parens)]
+                let items = IntoIterator::into_iter(#for_expr)
+                    .map(|#pat| ((#key).clone(), #mod_name::Props { #(#init_props,)* }))
+                    .collect::<Vec<_>>();
+                reconcile_keyed(
+                    &mut self.#dom_name,
+                    items,
+                    self.#empty_after.unchecked_ref(),
+                    |i, props| #mod_name::DOM::from_state(self.state.clone(), self.component_id, i, props),
+                    |dom, props| {
+                        dom.props = props;
+                        dom.update()
+                    },
+                )?;
+            );
+            self.mount.push(reconcile.to_string());
+            self.update.push(
+                quote!(
+                    if self.state.borrow().updated_idents.intersection(&HashSet::from(#used_state_idents)).count() > 0 {
+                        #reconcile
+                    }
+                )
+                .to_string(),
+            );
+        } else {
+            self.mount.push(
+                quote!(
+                let mut dom_index = 0;
+                #for_loop_mount
+                )
+                .to_string(),
+            );
 
-                let for_expr = &for_loop_mount.expr;
-                self.mount.push(
-                    quote!(
-                    let mut i = 0;
-                    #for_loop_mount
-                    )
-                    .to_string(),
-                );
+            self.update.push(
+                quote!(
+                    // for dom in self.#dom_name.iter_mut() {
+                    //     dom.update()?;
+                    // }
 
-                self.update.push(
-                    quote!(
-                        // for dom in self.#dom_name.iter_mut() {
-                        //     dom.update()?;
-                        // }
-
-                        // ForLoop's expr idents and ForLoop's block could be differentiate for optimization :
-                        // - When only expr has changed with no props, we don't need to run dom.update()
-                        // - When block has changed, we don't need to update props only to update
-                        if self.state.borrow().updated_idents.intersection(&HashSet::from(#used_state_idents)).count() > 0  {
-                            let mut new_instances = Vec::new();
-                            let mut truncate_index = None;
-                            #[allow(unused_parens)]
-                            for (i, pair) in (#for_expr).zip_longest(self.#dom_name.iter_mut()).enumerate()
-                            {
-                                match pair {
-                                    #[allow(unused_parens)]
-                                    EitherOrBoth::Both((#(#loop_idents),*), dom) => {
-                                        #(#update_props)*
-                                        dom.update()?;
-                                    }
-                                    #[allow(unused_parens)]
-                                    EitherOrBoth::Left((#(#loop_idents),*)) => {
-                                        let props = #mod_name::Props { #(#init_props,)* };
-                                        let mut dom_instance = #mod_name::DOM::from_state(self.state.clone(), i as u32, props)?;
-                                        dom_instance.mount(self.#empty_after.unchecked_ref())?;
-                                        new_instances.push(dom_instance)
-                                    }
-                                    EitherOrBoth::Right(_) => {
-                                        truncate_index = Some(i);
-                                        break;
-                                    }
+                    // ForLoop's expr idents and ForLoop's block could be differentiate for optimization :
+                    // - When only expr has changed with no props, we don't need to run dom.update()
+                    // - When block has changed, we don't need to update props only to update
+                    if self.state.borrow().updated_idents.intersection(&HashSet::from(#used_state_idents)).count() > 0  {
+                        let mut new_instances = Vec::new();
+                        let mut truncate_index = None;
+                        #[allow(unused_parens)]
+                        for (dom_index, pair) in (#for_expr).zip_longest(self.#dom_name.iter_mut()).enumerate()
+                        {
+                            match pair {
+                                #[allow(unused_parens)]
+                                EitherOrBoth::Both(#pat, dom) => {
+                                    #(#update_props)*
+                                    dom.update()?;
+                                }
+                                #[allow(unused_parens)]
+                                EitherOrBoth::Left(#pat) => {
+                                    let props = #mod_name::Props { #(#init_props,)* };
+                                    let mut dom_instance = #mod_name::DOM::from_state(self.state.clone(), self.component_id, dom_index as u32, props)?;
+                                    dom_instance.mount(self.#empty_after.unchecked_ref())?;
+                                    new_instances.push(dom_instance)
+                                }
+                                EitherOrBoth::Right(_) => {
+                                    truncate_index = Some(dom_index);
+                                    break;
                                 }
-                            }
-                            match truncate_index {
-                                Some(index) => self.#dom_name.truncate(index as usize),
-                                None => self.#dom_name.append(&mut new_instances),
                             }
                         }
-                    )
-                    .to_string(),
-                );
+                        match truncate_index {
+                            Some(index) => self.#dom_name.truncate(index as usize),
+                            None => self.#dom_name.append(&mut new_instances),
+                        }
+                    }
+                )
+                .to_string(),
+            );
+        }
 
-                println!("Children in for loop are :\n{:#?}", children);
-                let sub_dom = Dom::generate(children, details, props, self._state.clone(), false);
-                // sub_dom.props.extend(
-                //     props
-                //         .iter()
-                //         .map(|(f, type_)| {
-                //             if type_.starts_with('&') {
-                //                 (f.clone(), type_.clone())
-                //             } else {
-                //                 (f.clone(), type_.clone())
-                //             }
-                //         })
-                //         .collect::<Vec<_>>(),
-                // );
-                // let mut dom = Dom::default(self._state.clone());
-                // dom._append_nodes = false;
-                // dom.generate_elements("target", children, details);
-                self.sub_doms.insert(name.clone(), sub_dom);
-            }
-            _ => {
-                expr.for_token.span.warning(format!("For loop is useless, for now only Pat::Ident are supported. To implement another Pat see {}:{}", file!(), line!()));
-                return;
-            }
-        };
+        println!("Children in for loop are :\n{:#?}", children);
+        let sub_dom = Dom::generate(children, details, props, self._state.clone(), false);
+        // sub_dom.props.extend(
+        //     props
+        //         .iter()
+        //         .map(|(f, type_)| {
+        //             if type_.starts_with('&') {
+        //                 (f.clone(), type_.clone())
+        //             } else {
+        //                 (f.clone(), type_.clone())
+        //             }
+        //         })
+        //         .collect::<Vec<_>>(),
+        // );
+        // let mut dom = Dom::default(self._state.clone());
+        // dom._append_nodes = false;
+        // dom.generate_elements("target", children, details);
+        self.sub_doms.insert(name.clone(), sub_dom);
+    }
+}
+
+fn idents_updated(idents: &HashSet<String>) -> String {
+    //! Code checking if one of the state's idents has been updated.
+    format!(
+        "self.state.borrow().updated_idents.intersection(&HashSet::from([{}])).count() > 0",
+        idents
+            .iter()
+            .map(|ident| format!(r#""{}".to_string()"#, ident))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+fn written_states(expr: &Expr, details: &LocalDetails) -> Vec<String> {
+    //! States the expression writes, like the ones recorded by handler closures.
+    let mut ident_modifier = details.get_ident_modifier("state");
+    ident_modifier.visit_expr_mut(&mut expr.clone());
+    let mut written: Vec<String> = ident_modifier.names_refmut.into_iter().collect();
+    written.sort();
+    written
+}
+
+fn quoted(idents: &[String]) -> String {
+    idents
+        .iter()
+        .map(|ident| format!(r#""{ident}""#))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn record_written(idents: &[String]) -> String {
+    //! Code recording the written states, the component is then updated at the next tick.
+    if idents.is_empty() {
+        return String::new();
+    }
+    let inserts = idents
+        .iter()
+        .map(|ident| format!(r#"state.updated_idents.insert("{ident}".to_string());"#))
+        .collect::<String>();
+    format!(
+        "{{ let mut state = self.state.borrow_mut(); {inserts} \
+        state.runtime.borrow_mut().dirty_ids.insert(self.component_id); }}"
+    )
+}
+
+fn block_to_expr(block: &Block) -> Expr {
+    //! Unwrap `{expr}` so the value is borrowed instead of moved out of the state.
+    match block.stmts.as_slice() {
+        [syn::Stmt::Expr(expr)] => expr.clone(),
+        _ => Expr::Block(ExprBlock {
+            attrs: Vec::new(),
+            label: None,
+            block: block.clone(),
+        }),
+    }
+}
+
+fn update_needed(idents: &HashSet<String>, use_props: bool) -> String {
+    // Props and stores are not tracked by updated_idents, so code reading them is always run
+    if use_props {
+        "true".to_string()
+    } else {
+        idents_updated(idents)
     }
 }
 
 fn ident_from(name: String) -> syn::Ident {
     syn::parse_str(name.as_str()).unwrap()
 }
+
+fn match_bindings(details: &LocalDetails, expr: &Expr, cases: &[Case]) -> Option<Vec<PatBinding>> {
+    //! Bindings of the cases typed from the states' types as owned props, the matched value
+    //! is a clone.
+    let ty = types::owned(&types::expr_type(expr, &details.states, &details.items)?);
+    let mut bindings = Vec::new();
+    for Case { pat, .. } in cases {
+        for (ident, ty) in types::pat_bindings(pat, &ty, &details.items)? {
+            let ty = types::owned(&ty);
+            bindings.push(PatBinding {
+                name: ident.to_string(),
+                ty: quote!(#ty).to_string(),
+            });
+        }
+    }
+    Some(bindings)
+}
+
+fn query_bindings(
+    details: &LocalDetails,
+    code: String,
+    span: Span,
+) -> syn::Result<Vec<PatBinding>> {
+    //! Bindings typed by rustc, as owned props. Errors are given at `span`.
+    let bindings = details.pat_bindings(code, span)?;
+    Ok(bindings
+        .into_iter()
+        .map(|PatBinding { name, ty }| match syn::parse_str(&ty) {
+            Ok(ty) => {
+                let ty = types::owned(&ty);
+                PatBinding {
+                    name,
+                    ty: quote!(#ty).to_string(),
+                }
+            }
+            Err(_) => PatBinding { name, ty },
+        })
+        .collect())
+}
+
+fn match_query(details: &LocalDetails, expr: &Expr, cases: &[Case]) -> String {
+    //! Cases typed by rustc, the other patterns of the file's items (or of other crates).
+    let pats = cases.iter().map(|case| &case.pat);
+    format!(
+        "{}\n{}",
+        details.read_states_code(quote!(#expr)),
+        quote!(match (#expr).clone() { #(#pats => {},)* _ => {} }),
+    )
+}
+
+fn for_item_bindings(
+    details: &LocalDetails,
+    expr: &ExprForLoop,
+) -> syn::Result<Vec<(Ident, Type)>> {
+    //! Bindings of a loop's pattern typed from the states' types, as owned props.
+    let item = types::iter_item_type(&expr.expr, &details.states, &details.items).ok_or_else(|| {
+        syn::Error::new_spanned(
+            &expr.expr,
+            "Can't find the type of the items, iterate over a typed state or its fields (ex: `let visible: Vec<Item> = $ items.iter().filter(..).cloned().collect();`).",
+        )
+    })?;
+    let bindings = types::pat_bindings(&expr.pat, &item, &details.items).ok_or_else(|| {
+        syn::Error::new_spanned(
+            &expr.pat,
+            format!(
+                "Can't type this pattern with items of type `{}`, only tuple patterns and patterns of the structs of this file are supported.",
+                quote!(#item)
+            ),
+        )
+    })?;
+    Ok(bindings
+        .into_iter()
+        .map(|(ident, ty)| (ident, types::owned(&ty)))
+        .collect())
+}
+
+fn settled_bindings(
+    details: &LocalDetails,
+    future: &Expr,
+    pat: &syn::Pat,
+    variant: Option<&str>,
+) -> Option<Vec<PatBinding>> {
+    //! Bindings of a then / catch pattern typed from the future's output, as owned props.
+    let output = types::future_output_type(future, &details.states, &details.items)?;
+    let pat: syn::Pat = match variant {
+        Some(variant) => {
+            let variant = ident_from(variant.to_string());
+            syn::parse_quote!(#variant(#pat))
+        }
+        None => pat.clone(),
+    };
+    let bindings = types::pat_bindings(&pat, &output, &details.items)?;
+    Some(
+        bindings
+            .into_iter()
+            .map(|(ident, ty)| {
+                let ty = types::owned(&ty);
+                PatBinding {
+                    name: ident.to_string(),
+                    ty: quote!(#ty).to_string(),
+                }
+            })
+            .collect(),
+    )
+}
+
+fn settled_query(
+    details: &LocalDetails,
+    future: &Expr,
+    pat: &syn::Pat,
+    variant: Option<&str>,
+) -> String {
+    let arm = match variant {
+        Some(variant) => {
+            let variant = ident_from(variant.to_string());
+            quote!(std::task::Poll::Ready(#variant(#pat)))
+        }
+        None => quote!(std::task::Poll::Ready(#pat)),
+    };
+    // The future's output is typed without awaiting it
+    format!(
+        "{}\n{}",
+        details.read_states_code(quote!(#future)),
+        quote!(
+            match std::future::Future::poll(
+                std::pin::pin!(#future),
+                &mut std::task::Context::from_waker(std::task::Waker::noop()),
+            ) {
+                #arm => {},
+                _ => {}
+            }
+        ),
+    )
+}
+
+pub fn type_queries(elements: &[Element], details: &LocalDetails) -> Vec<(String, Span)> {
+    //! Codes typing the bindings of the markup's patterns, to find them all with one rustc
+    //! invocation before generating the doms. Their errors are given at the pattern's span.
+    let mut queries = Vec::new();
+    for element in elements {
+        match element {
+            Element::Classic(Classic { children, .. }) => {
+                queries.extend(type_queries(children, details))
+            }
+            Element::ExprElement(ExprElement::For { children, .. }) => {
+                queries.extend(type_queries(children, details))
+            }
+            Element::ExprElement(ExprElement::If { conditions }) => {
+                for Condition { children, .. } in conditions {
+                    queries.extend(type_queries(children, details));
+                }
+            }
+            Element::ExprElement(ExprElement::Match { expr, cases }) => {
+                if match_bindings(details, expr, cases).is_none() {
+                    queries.push((match_query(details, expr, cases), expr.span()));
+                }
+                for Case { children, .. } in cases {
+                    queries.extend(type_queries(children, details));
+                }
+            }
+            Element::ExprElement(ExprElement::Await {
+                future,
+                pending,
+                then,
+                catch,
+            }) => {
+                queries.extend(type_queries(pending, details));
+                let variant = catch.as_ref().map(|_| "Ok");
+                for (settled, variant) in [(then, variant), (catch, Some("Err"))] {
+                    if let Some(Settled { pat, children }) = settled {
+                        if settled_bindings(details, future, pat, variant).is_none() {
+                            queries
+                                .push((settled_query(details, future, pat, variant), pat.span()));
+                        }
+                        queries.extend(type_queries(children, details));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    queries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_bindings, match_query, settled_bindings, Dom, State};
+    use crate::{
+        check::check_ident_expr,
+        html::{replace_store_sigils, Element, ExprElement, Root},
+        state_block::{extract_locals, parse_state_block},
+        template::{component::clean_up_generated, Component},
+        types::FileItems,
+    };
+    use sailfish::TemplateOnce;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::{cell::RefCell, rc::Rc};
+    use syn::{parse::Parser, visit::Visit};
+
+    fn generated(file: &str, block: &str, markup: &str) -> syn::Result<syn::File> {
+        //! Code of a root component after the file's items, parsed back : the generated code
+        //! is valid Rust.
+        let mut items = syn::parse_file(file)?;
+        let block = parse_state_block.parse_str(block)?;
+        let mut details = extract_locals(&block, FileItems::from_items(&items.items))?;
+        let Root(elements) = syn::parse2(replace_store_sigils(syn::parse_str(markup)?))?;
+        check_ident_expr(&elements, &details)?;
+        let component = Component::new(None, &mut details, &elements)?;
+        let generated: syn::File =
+            syn::parse_str(&clean_up_generated(component.render_once().unwrap()))?;
+        items.items.extend(generated.items);
+        Ok(items)
+    }
+
+    /// Lowercase paths of one segment used by the code, and the names it declares.
+    #[derive(Default)]
+    struct Idents {
+        declared: HashSet<String>,
+        used: Vec<String>,
+    }
+
+    impl<'ast> Visit<'ast> for Idents {
+        fn visit_pat_ident(&mut self, node: &'ast syn::PatIdent) {
+            self.declared.insert(node.ident.to_string());
+            syn::visit::visit_pat_ident(self, node);
+        }
+
+        fn visit_signature(&mut self, node: &'ast syn::Signature) {
+            self.declared.insert(node.ident.to_string());
+            syn::visit::visit_signature(self, node);
+        }
+
+        fn visit_use_name(&mut self, node: &'ast syn::UseName) {
+            self.declared.insert(node.ident.to_string());
+        }
+
+        fn visit_use_rename(&mut self, node: &'ast syn::UseRename) {
+            self.declared.insert(node.rename.to_string());
+        }
+
+        fn visit_expr_path(&mut self, node: &'ast syn::ExprPath) {
+            if let Some(ident) = node.path.get_ident() {
+                let name = ident.to_string();
+                if name.starts_with(|c: char| c.is_lowercase()) && name != "self" {
+                    self.used.push(name);
+                }
+            }
+            syn::visit::visit_expr_path(self, node);
+        }
+    }
+
+    fn undeclared(file: &syn::File) -> Vec<String> {
+        //! Names used but declared nowhere in the generated code (its macros aren't seen).
+        let mut idents = Idents::default();
+        idents.visit_file(file);
+        let mut undeclared: Vec<String> = idents
+            .used
+            .into_iter()
+            .filter(|name| !idents.declared.contains(name))
+            .collect();
+        undeclared.dedup();
+        undeclared
+    }
+
+    fn state() -> Rc<RefCell<State>> {
+        Rc::new(RefCell::new(State {
+            init_block: String::new(),
+            fields_type: Vec::new(),
+            fields_value: Vec::new(),
+            callbacks: Vec::new(),
+            stores: BTreeSet::new(),
+        }))
+    }
+
+    #[test]
+    fn user_enum_match() -> syn::Result<()> {
+        let file = syn::parse_file(
+            "use serde::Deserialize;
+            #[derive(Clone, Deserialize)]
+            enum Status { Loading, Loaded(Vec<Todo>), Failed { reason: String, code: u16 } }
+            async fn load() -> Vec<Todo> { fetch().await }",
+        )?;
+        let block = parse_state_block.parse_str(
+            "{
+                let status: Status = Status::Loading;
+                let todos: Vec<Todo> = Vec::new();
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::from_items(&file.items))?;
+        let element: Element = syn::parse_str(
+            "{match status}
+                {case Status::Loaded(todos)}<p>{todos.len()}</p>
+                {case Status::Failed { reason, .. }}<p>{reason}</p>
+                {case Loading}<p>loading</p>
+            {/match}",
+        )?;
+        let (expr, cases) = match element {
+            Element::ExprElement(ExprElement::Match { expr, cases }) => (expr, cases),
+            _ => panic!("Element doesn't match."),
+        };
+        let bindings = match_bindings(&details, &expr, &cases)
+            .expect("the cases are typed")
+            .into_iter()
+            .map(|binding| format!("{}: {}", binding.name, binding.ty))
+            .collect::<Vec<_>>();
+        assert_eq!(bindings, ["todos: Vec < Todo >", "reason: String"]);
+
+        // rustc only sees the matched state and the file's items, without their bodies
+        let query = match_query(&details, &expr, &cases);
+        assert!(query.contains("let status : Status = unimplemented ! () ;"));
+        assert!(!query.contains("let todos"));
+        let declarations = &details.items.declarations;
+        assert!(declarations.contains("# [derive (Clone)] enum Status"));
+        assert!(declarations.contains("async fn load () -> Vec < Todo > { unimplemented ! () }"));
+        assert!(!declarations.contains("serde"));
+        Ok(())
+    }
+
+    #[test]
+    fn user_async_fn_await() -> syn::Result<()> {
+        let file = syn::parse_file(
+            "struct Todo { id: u32 }
+            async fn load(page: u32) -> Result<Vec<Todo>, String> { fetch(page).await }
+            impl Todo { async fn count() -> usize { 0 } }",
+        )?;
+        let block = parse_state_block.parse_str("{ let page: u32 = 0; }")?;
+        let details = extract_locals(&block, FileItems::from_items(&file.items))?;
+        let bindings = |future: &str, pat: &str, variant: Option<&str>| {
+            let future = syn::parse_str(future).unwrap();
+            let pat = syn::parse_str(pat).unwrap();
+            settled_bindings(&details, &future, &pat, variant)
+                .expect("the output is typed")
+                .into_iter()
+                .map(|binding| format!("{}: {}", binding.name, binding.ty))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            bindings("load(page)", "todos", Some("Ok")),
+            ["todos: Vec < Todo >"]
+        );
+        assert_eq!(
+            bindings("load(page)", "error", Some("Err")),
+            ["error: String"]
+        );
+        assert_eq!(bindings("Todo::count()", "count", None), ["count: usize"]);
+        assert_eq!(bindings("async { page + 1 }", "next", None), ["next: u32"]);
+        Ok(())
+    }
+
+    #[test]
+    fn component_in_loop_row() -> syn::Result<()> {
+        let block = parse_state_block.parse_str("{ let labels: Vec<String> = Vec::new(); }")?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let elements = vec![syn::parse_str::<Element>(
+            "<div>{for label in labels.iter()}<Item label={label.clone()}/>{/for}</div>",
+        )?];
+        let dom = Dom::generate_root(&elements, &details, HashMap::new(), state(), true);
+
+        // `get_context` walks the parents, the row's index must not be recorded as one
+        fn component_inits(dom: &Dom) -> Vec<String> {
+            let inits = dom.init.iter().filter(|init| init.contains("with_props"));
+            let sub_inits = dom.sub_doms.values().flat_map(component_inits);
+            inits.cloned().chain(sub_inits).collect()
+        }
+        let inits = component_inits(&dom);
+        assert_eq!(inits.len(), 1);
+        assert!(inits[0].contains("Some(component_id)"));
+        assert!(!inits[0].contains("Some(id)"));
+        Ok(())
+    }
+
+    #[test]
+    fn effect_and_hook_writes() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let count: u32 = 1;
+                let doubled: u32 = 0;
+                let mounted: bool = false;
+                doubled = count * 2;
+                let on_mount = || mounted = true;
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let elements = vec![syn::parse_str::<Element>("<p>{doubled} {mounted}</p>")?];
+        let dom = Dom::generate_root(&elements, &details, HashMap::new(), state(), true);
+
+        let mount = dom.mount_mounted.join("\n");
+        assert!(mount.contains(r#"state.updated_idents.insert("doubled".to_string());"#));
+        assert!(mount.contains(r#"state.updated_idents.insert("mounted".to_string());"#));
+        assert!(mount.contains("dirty_ids.insert(self.component_id)"));
+        // The effect reruns when `count` changes, not when it writes `doubled`
+        let update = dom.update.join("\n");
+        assert!(update.contains(r#"HashSet::from(["count".to_string()])"#));
+        assert!(update.contains(r#"written_idents.extend(["doubled"])"#));
+        assert!(update.contains("dirty_ids.insert(self.component_id)"));
+        Ok(())
+    }
+
+    #[test]
+    fn effect_without_writes() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let count: u32 = 1;
+                save(count);
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let elements = vec![syn::parse_str::<Element>("<p>{count}</p>")?];
+        let dom = Dom::generate_root(&elements, &details, HashMap::new(), state(), true);
+
+        // Nothing is recorded, `written_idents` isn't declared
+        let update = dom.update.join("\n");
+        assert!(update.contains("save (self . state . borrow () . count)"));
+        assert!(!update.contains("written_idents"));
+        Ok(())
+    }
+
+    #[test]
+    fn guarded_writes() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let step: u32 = 1;
+                let total: u32 = 0;
+                total = step * 2;
+                let on_mount = || total += step;
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let elements = vec![syn::parse_str::<Element>("<p>{total}</p>")?];
+        let dom = Dom::generate_root(&elements, &details, HashMap::new(), state(), true);
+
+        // The state is borrowed once, a read doesn't overlap the mutable borrow
+        let code = [dom.mount_mounted, dom.update].concat().join("\n");
+        assert!(code.contains("let mut guard = self . state . borrow_mut () ;"));
+        assert!(code.contains("state . total = state . step * 2"));
+        assert!(code.contains("state . total += state . step"));
+        assert!(!code.contains("borrow_mut () . total"));
+        Ok(())
+    }
+
+    #[test]
+    fn generated_counter() -> syn::Result<()> {
+        let file = generated(
+            "",
+            "{ let count: u32 = 0; }",
+            "<button on:click={count += 1}>{count}</button>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_bindings() -> syn::Result<()> {
+        let file = generated(
+            "",
+            "{ let count: u32 = 0; let name: String = String::new(); }",
+            "<div><input bind:value={count}/><input bind:value={name}/></div>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        // Numbers are bound as numbers, the other values are displayed
+        let code = quote::quote!(#file).to_string();
+        assert!(code.contains("set_value_as_number (self . state . borrow () . count as f64)"));
+        assert!(code.contains("set_value (& format ! (\"{}\" , self . state . borrow () . name))"));
+        Ok(())
+    }
+
+    #[test]
+    fn generated_derived() -> syn::Result<()> {
+        let file = generated(
+            "",
+            "{ let count: u32 = 0; let doubled: u32 = $ count * 2; }",
+            "<button on:click={count += 1}>{doubled}</button>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_effects() -> syn::Result<()> {
+        let file = generated(
+            "fn save(count: u32) {}",
+            "{
+                let count: u32 = 0;
+                let doubled: u32 = 0;
+                save(count);
+                doubled = count * 2;
+            }",
+            "<button on:click={count += 1}>{doubled}</button>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        // An effect writing nothing
+        let file = generated(
+            "fn save(count: u32) {}",
+            "{ let count: u32 = 0; save(count); }",
+            "<button on:click={count += 1}>{count}</button>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_hooks() -> syn::Result<()> {
+        let file = generated(
+            "fn stop() {}",
+            "{
+                let count: u32 = 0;
+                let mounted: bool = false;
+                let on_mount = || mounted = true;
+                let before_update = || count += 1;
+                let after_update = || stop();
+                #[on_destroy]
+                let clean = || stop();
+            }",
+            "<p>{count} {mounted}</p>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_stores() -> syn::Result<()> {
+        let file = generated(
+            "",
+            "{
+                let count: stores::Writable<u32> = stores::Writable::new(0);
+                let total: u32 = 0;
+                let add = |_| total = $count + 1;
+            }",
+            "<button on:click={count.set($count + 1)}>{$count}</button>
+            <button on:click={add}>{total}</button>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_contexts() -> syn::Result<()> {
+        let file = generated(
+            "",
+            "{
+                set_context(\"theme\", \"dark\".to_string());
+                let labels: Vec<String> = Vec::new();
+            }",
+            "<div>{for label in labels.iter()}<Item label={label.clone()}/>{/for}</div>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_guarded_writes() -> syn::Result<()> {
+        let file = generated(
+            "struct Point { x: u32 }",
+            "{
+                let point: Point = Point { x: 0 };
+                let step: u32 = 1;
+                point.x = step;
+                let on_mount = || point.x += step;
+            }",
+            "<button on:click={point.x = step}>{point.x}</button>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_blocks() -> syn::Result<()> {
+        let file = generated(
+            "enum Status { Loading, Loaded(Vec<String>) }
+            struct Todo { id: u32, label: String }
+            async fn load(page: u32) -> Result<String, String> { Ok(String::new()) }",
+            "{
+                let page: u32 = 0;
+                let status: Status = Status::Loading;
+                let todos: Vec<Todo> = Vec::new();
+            }",
+            "<div title={page}>
+                {if page > 0}<p>next</p>{else}<p>first</p>{/if}
+                {for todo in todos.iter(); key = todo.id}<p>{todo.label.clone()}</p>{/for}
+                {match status}
+                    {case Status::Loaded(labels)}<p>{labels.len()}</p>
+                    {case Loading}<p>loading</p>
+                {/match}
+                {await load(page)}<p>wait</p>{then label}<p>{label}</p>{catch error}<p>{error}</p>{/await}
+            </div>",
+        )?;
+        assert_eq!(undeclared(&file), Vec::<String>::new());
+        Ok(())
+    }
+}
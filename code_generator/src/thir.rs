@@ -1,364 +1,543 @@
 use std::{
     collections::HashMap,
-    ffi::OsString,
-    fs,
-    io::Read,
-    path::Path,
+    env, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Component, Path, PathBuf},
     process::{Command, Output},
-    str::Chars,
 };
 
-const DIR_THIR_ARTEFACTS: &str = "target/thir/";
+/// Error of a stable rustc, the THIR is unstable.
+pub const NIGHTLY_NEEDED: &str = "The type queries need a nightly rustc, set `RUSTC` to one (ex: `RUSTC=$(rustup which --toolchain nightly rustc)`).";
 
-fn generate_rustc_thir<P: AsRef<Path>>(path: P) -> Output {
-    Command::new("rustc")
+fn generate_rustc_thir<P: AsRef<Path>>(path: P) -> Result<Output, String> {
+    //! The THIR output is unstable, it needs a nightly `rustc` (or the one of `RUSTC`).
+    Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()))
         .arg("-Z")
         .arg("unpretty=thir-flat")
+        .arg("--edition")
+        .arg("2021")
         .arg("--color")
-        .arg("always")
+        .arg("never")
         .arg("--allow")
         .arg("unused_variables")
-        .arg(path.as_ref().to_str().expect("Unvalid Path to str."))
+        .arg("--allow")
+        .arg("dead_code")
+        .arg(path.as_ref())
         .output()
-        .expect("failed to execute process")
+        .map_err(|error| format!("Failed to execute rustc : {error}"))
 }
 
-fn write_code_and_thir(path_code: &Path, out: &Path, err: &Path, code: &str) -> (String, String) {
-    fs::write(path_code, code).expect("Can't write CODE.");
-    let output = &generate_rustc_thir(path_code);
-    let stdout = String::from_utf8(output.stdout.to_owned()).expect("Can't parse THIR utf-8.");
-    let stderr = String::from_utf8(output.stderr.to_owned()).expect("Can't parse Stderr as utf-8.");
-    fs::write(out, stdout.clone()).expect("Can't write THIR.");
-    fs::write(err, stderr.clone()).expect("Can't write THIR.");
+fn thir_artefacts_dir(file: &str) -> PathBuf {
+    //! Artefacts of a source file, in the target directory of the crate being compiled.
+    let target = match (
+        env::var_os("OUT_DIR"),
+        env::var_os("CARGO_TARGET_DIR"),
+        env::var_os("CARGO_MANIFEST_DIR"),
+    ) {
+        (Some(out_dir), _, _) => PathBuf::from(out_dir),
+        (None, Some(target_dir), _) => PathBuf::from(target_dir),
+        (None, None, Some(manifest_dir)) => Path::new(&manifest_dir).join("target"),
+        (None, None, None) => PathBuf::from("target"),
+    };
+    // `src/main.rs` gives `thir/src/main.rs/`, the path can't go out of the directory
+    let file = Path::new(file)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect::<PathBuf>();
+    match file.as_os_str().is_empty() {
+        true => target.join("thir").join("unknown_file_name"),
+        false => target.join("thir").join(file),
+    }
+}
 
-    (stdout, stderr)
+fn thir_hash(code: &str) -> String {
+    //! Name of the code's artefacts, the rustc used is hashed with the code.
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    env::var_os("RUSTC").hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
 }
 
-fn generate_thir(code: &str, file_name: OsString, code_name: String) -> String {
-    let path_thir_artefacts: &Path = Path::new(DIR_THIR_ARTEFACTS);
-    let path_file = &path_thir_artefacts.join(file_name);
-    let path_code = &path_file.join(format!("{code_name}.rs"));
-    let path_info = &path_file.join(format!("{code_name}.txt"));
-    let path_errors = &path_file.join(format!("{code_name}.stderr"));
-    fs::create_dir_all(path_file).expect("Can't create THIR directory.");
-
-    let (stdout, stderr) = match fs::File::open(path_code) {
-        Ok(mut file_info) => {
-            let mut content = String::new();
-            file_info
-                .read_to_string(&mut content)
-                .expect("Can't read CODE file.");
-            if code != content {
-                write_code_and_thir(path_code, path_info, path_errors, code)
-            } else {
-                (
-                    fs::read_to_string(path_info).expect("Can't read THIR file."),
-                    fs::read_to_string(path_errors).expect("Can't read THIR file."),
-                )
-            }
+fn generate_thir(code: &str, file: &str) -> Result<String, String> {
+    //! THIR of the code, cached by the code's hash with rustc's failures. Parallel
+    //! expansions wait on a lock file, then the first one's THIR is read.
+    let hash = thir_hash(code);
+    let dir = thir_artefacts_dir(file);
+    fs::create_dir_all(&dir).map_err(|error| format!("Can't create THIR directory : {error}"))?;
+    let path_code = dir.join(format!("{hash}.rs"));
+    let path_info = dir.join(format!("{hash}.txt"));
+    let path_errors = dir.join(format!("{hash}.stderr"));
+    let path_failure = dir.join(format!("{hash}.error"));
+
+    let lock = fs::File::create(dir.join(format!("{hash}.lock")))
+        .map_err(|error| format!("Can't create THIR lock : {error}"))?;
+    lock.lock()
+        .map_err(|error| format!("Can't lock THIR : {error}"))?;
+    // The THIR or the failure is written last, its presence means the artefacts are complete.
+    // The hash isn't trusted : the artefacts of another code are replaced.
+    if fs::read_to_string(&path_code).is_ok_and(|cached| cached == code) {
+        if let Ok(stdout) = fs::read_to_string(&path_info) {
+            return Ok(stdout);
+        }
+        if let Ok(failure) = fs::read_to_string(&path_failure) {
+            return Err(failure);
         }
-        Err(_) => write_code_and_thir(path_code, path_info, path_errors, code),
+    }
+    for path in [&path_info, &path_failure] {
+        if path.exists() {
+            fs::remove_file(path).map_err(|error| format!("Can't remove THIR : {error}"))?;
+        }
+    }
+    fs::write(&path_code, code).map_err(|error| format!("Can't write CODE : {error}"))?;
+    let output = generate_rustc_thir(&path_code)?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    fs::write(&path_errors, &stderr)
+        .map_err(|error| format!("Can't write THIR errors : {error}"))?;
+    let thir = if stderr.contains("only accepted on the nightly compiler") {
+        Err(NIGHTLY_NEEDED.to_string())
+    } else if !output.status.success() {
+        Err(format!(
+            "rustc failed on {} :\n{stderr}",
+            path_code.display()
+        ))
+    } else {
+        Ok(stdout)
     };
-    println!("{}\n\n{}", stdout, stderr);
-    stdout
-}
-
-// struct Expr {
-//     kind: ...,
-//     ty: ...,
-//     temp_lifetime: Option<Node>,
-//     span: Span,
-// }
-
-// struct Block {
-//     targeted_by_break: bool,
-//     region_scope: Node,
-//     opt_destruction_scope: Option<Destruction>,
-//     span: Span,
-//     stmts: Vec<Stmt>,
-//     expr: Option<Id>,
-//     safety_mode: Safe,
-// }
-
-#[derive(Debug, Default)]
-struct Location {
-    line: u32,
-    column: u32,
+    match &thir {
+        Ok(stdout) => fs::write(&path_info, stdout),
+        Err(failure) => fs::write(&path_failure, failure),
+    }
+    .map_err(|error| format!("Can't write THIR : {error}"))?;
+    thir
 }
 
-impl Location {
-    fn from_iterator<'a, I>(iterator: &mut I) -> Option<Self>
-    where
-        I: Iterator<Item = u32> + 'a,
-    {
-        Some(Location {
-            line: iterator.next()?,
-            column: iterator.next()?,
-        })
-    }
+/// Position in a query's code, lines and columns start at 1.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: u32,
+    pub column: u32,
 }
 
-#[derive(Debug)]
-struct Span {
-    file: String,
-    start: Location,
-    end: Location,
+/// Place of an expression, its file is the batched code.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
 }
 
-impl From<String> for Span {
-    /// target/thir/src/main.rs/for_loop_1.rs:6:9:6:13(#0)
-    fn from(value: String) -> Self {
-        let mut file = String::new();
-        let mut positions = Vec::new();
-
-        let mut buf = String::new();
-        let mut file_parsed = false;
-        for c in value.chars() {
-            match c {
-                ':' => {
-                    if file_parsed {
-                        positions.push(buf.clone());
-                        // match str::parse(buf.as_str()) {
-                        //     Ok(num) => positions.push(num),
-                        //     Err(_) => positions.push(0),
-                        // }
-                    } else {
-                        file = buf.clone();
-                        file_parsed = true;
-                    }
-                    buf.clear();
-                }
-                '(' if file_parsed => break,
-                _ => buf.push(c),
-            }
+impl From<&str> for Span {
+    /// target/thir/src/main.rs/8f3a2c1d9e0b4a67.rs:6:9: 6:13 (#0)
+    fn from(value: &str) -> Self {
+        let value = value.split(" (#").next().unwrap_or(value);
+        // The file may contain `:`, the positions are the last parts
+        let parts: Vec<&str> = value.rsplitn(5, ':').collect();
+        let number = |i: usize| {
+            parts
+                .get(i)
+                .and_then(|part| part.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            start: Location {
+                line: number(3),
+                column: number(2),
+            },
+            end: Location {
+                line: number(1),
+                column: number(0),
+            },
         }
-        positions.push(buf.clone());
-        let positions: Vec<u32> = positions.iter().map(|s| s.parse().unwrap_or(0)).collect();
-        let mut iter_position = positions.iter().cloned();
-        let start = Location::from_iterator(&mut iter_position).unwrap_or_default();
-        let end = Location::from_iterator(&mut iter_position).unwrap_or_default();
-        Self { file, start, end }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PatBinding {
     pub name: String,
     pub ty: String,
-    // pub span: Span,
-    // mutability: Mutability,
-    // mode: Mode,
 }
 
-#[derive(Default)]
-struct RawInfo {
-    bindings: Vec<PatBinding>,
-    thir: Thir,
-    dbg_line: usize,
-    // exprs: Vec<Expr>,
+/// Type of an expression, where it is in the code.
+#[derive(Debug, Clone)]
+pub struct ExprType {
+    pub ty: String,
+    pub span: Span,
+}
+
+/// Types found in the THIR of a code : its bindings and every expression.
+#[derive(Debug, Clone, Default)]
+pub struct Types {
+    pub bindings: Vec<PatBinding>,
+    pub exprs: Vec<ExprType>,
+}
+
+impl Types {
+    pub fn expr_type(&self, start: Location, end: Location) -> Option<&str> {
+        //! Type of the expression at this place of the query's code, ex: to type a state from
+        //! its initializer. Scopes wrapping an expression have its span, they share its type.
+        self.exprs
+            .iter()
+            .find(|expr| expr.span.start == start && expr.span.end == end)
+            .map(|expr| expr.ty.as_str())
+    }
+
+    fn rebase(mut self, first_line: u32) -> Self {
+        //! Lines of the batched code become lines of the query's code, starting at `first_line`.
+        self.exprs.retain(|expr| expr.span.start.line >= first_line);
+        for expr in &mut self.exprs {
+            expr.span.start.line -= first_line - 1;
+            expr.span.end.line -= first_line - 1;
+        }
+        self
+    }
 }
 
 #[derive(Debug)]
 enum Thir {
-    List(Vec<Thir>),
+    /// `[...]` or `Name(...)`, their items are only walked
+    List,
     Fields((String, HashMap<String, Thir>)),
-    NamedList((String, Vec<Thir>)),
     Text(String),
 }
 
-impl Default for Thir {
-    fn default() -> Self {
-        Self::Text(String::new())
-    }
+/// Parser of a body printed by `-Z unpretty=thir-flat`, it's the pretty `Debug` of the THIR :
+/// a value opened at the end of a line (`Name {`, `Name(` or `[`) is closed on its own line.
+/// Its errors are given at the span of the markup which needed the query.
+struct RawInfo<'a> {
+    lines: std::iter::Enumerate<std::str::Lines<'a>>,
+    line: usize,
+    types: Types,
+    span: proc_macro2::Span,
 }
 
-macro_rules! instance_from_thir_raw_string {
-    ( $struct:ident: $map:ident => $( $name:ident ),* ) => {
-        {
-            $(
-                let $name = match $map.get(&stringify!($name).to_string()).unwrap() {
-                    Thir::Text(string) => string.to_owned(),
-                    _ => return,
-                };
-            )*
-            $struct { $(
-                $name: $name.into(),
-                )*
-            }
-        }
-    };
-}
-fn remove_last_char(buf: String) -> String {
-    let mut chars = buf.chars();
-    chars.next_back();
-    chars.collect()
-}
+impl<'a> RawInfo<'a> {
+    fn serialize(thir_raw: &'a str, span: proc_macro2::Span) -> syn::Result<Types> {
+        let mut info = Self {
+            lines: thir_raw.lines().enumerate(),
+            line: 0,
+            types: Types::default(),
+            span,
+        };
+        // `DefId(0:3 ~ crate[hash]::name):`
+        info.next_line()?;
+        let first = info.next_line()?;
+        info.value(first)?;
+        Ok(info.types)
+    }
+
+    fn error(&self, message: &str) -> syn::Error {
+        syn::Error::new(
+            self.span,
+            format!("Can't parse the THIR at line {} : {message}", self.line),
+        )
+    }
 
-impl RawInfo {
-    fn serialize(thir_raw: String) -> Result<Self, String> {
-        let mut s = Self::default();
-        let mut chars = thir_raw.chars();
-        for c in &mut chars {
-            if c == '\n' {
-                break;
+    fn next_line(&mut self) -> syn::Result<&'a str> {
+        match self.lines.next() {
+            Some((i, line)) => {
+                self.line = i + 1;
+                Ok(line.trim())
             }
+            None => Err(self.error("it ends before its closing delimiter")),
         }
-
-        s.thir = s.switch(&mut chars)?;
-        Ok(s)
     }
 
-    fn switch(&mut self, thir_raw: &mut Chars) -> Result<Thir, String> {
-        let mut buf = String::new();
-        let mut prev_c = ' ';
-        loop {
-            let c = match thir_raw.next() {
-                Some(next) => next,
-                None => Err("Triage as consumed whole chars.")?,
+    fn value(&mut self, text: &'a str) -> syn::Result<Thir> {
+        let text = text.strip_suffix(',').unwrap_or(text);
+        if let Some(name) = text.strip_suffix('{') {
+            let thir = Thir::Fields((name.trim().to_string(), self.fields()?));
+            self.insert_item(&thir);
+            Ok(thir)
+        } else if text.ends_with('(') {
+            self.items(')')?;
+            Ok(Thir::List)
+        } else if text.ends_with('[') {
+            self.items(']')?;
+            Ok(Thir::List)
+        } else {
+            // Quotes of strings are removed, inline values are kept as text
+            let text = match text
+                .strip_prefix('"')
+                .and_then(|text| text.strip_suffix('"'))
+            {
+                Some(string) => string,
+                None => text,
             };
-            match c {
-                '\n' => {
-                    self.dbg_line += 1;
-                    match prev_c {
-                        '{' => {
-                            let name = remove_last_char(buf);
-                            let thir = Thir::Fields((name, self.parse_map(thir_raw)));
-                            self.insert_item(&thir);
-                            return Ok(thir);
-                        }
-                        '[' => {
-                            let thir = Thir::List(self.parse_vec(thir_raw));
-                            self.insert_item(&thir);
-                            return Ok(thir);
-                        }
-                        '(' => {
-                            let name = remove_last_char(buf);
-                            return Ok(Thir::NamedList((name, self.parse_vec(thir_raw))));
-                        }
-                        ',' => {
-                            if buf.len() == 2 && "}])".contains(buf.chars().next().unwrap()) {
-                                return Err("Close delimiter.".to_string());
-                            } else {
-                                let mut chars = buf.chars();
-                                // remove last coma
-                                chars.next_back();
-                                let text = match (chars.next(), chars.next_back()) {
-                                    // remove double quote
-                                    (Some('"'), Some('"')) => chars.as_str().to_string(),
-                                    _ => remove_last_char(buf),
-                                };
-                                return Ok(Thir::Text(text));
-                            }
-                        }
-                        _ => panic!(
-                            "Triage : line must end with {{ or [ or ( but ends with '{}'",
-                            prev_c
-                        ),
-                    }
-                }
-                // chars that should not be pushed
-                ' ' => (),
-                _ => buf.push(c),
-            }
-            prev_c = c;
+            Ok(Thir::Text(text.to_string()))
         }
     }
 
-    fn parse_vec(&mut self, thir_raw: &mut Chars) -> Vec<Thir> {
-        let mut thirs = Vec::new();
+    fn items(&mut self, close: char) -> syn::Result<()> {
         loop {
-            let thir_raw_option = self.switch(thir_raw);
-            match thir_raw_option {
-                Ok(thir) => thirs.push(thir),
-                Err(_) => break,
+            let line = self.next_line()?;
+            if is_close(line, close) {
+                return Ok(());
             }
+            self.value(line)?;
         }
-        thirs
     }
 
-    fn parse_map(&mut self, thir_raw: &mut Chars) -> HashMap<String, Thir> {
-        let mut tree = HashMap::new();
-        let mut name = String::new();
+    fn fields(&mut self) -> syn::Result<HashMap<String, Thir>> {
+        let mut fields = HashMap::new();
         loop {
-            let c = match thir_raw.next() {
-                Some(next) => next,
-                None => break,
-            };
-            match c {
-                '}' => {
-                    break;
+            let line = self.next_line()?;
+            if is_close(line, '}') {
+                return Ok(fields);
+            }
+            match line.split_once(": ") {
+                Some((name, value)) => {
+                    let value = self.value(value)?;
+                    fields.insert(name.to_string(), value);
                 }
-                ':' => match self.switch(thir_raw) {
-                    Ok(thir) => {
-                        tree.insert(name.clone(), thir);
-                        name.clear();
-                    }
-                    Err(_) => break,
-                },
-                ' ' => (),
-                '\n' => self.dbg_line += 1,
-                ',' => (),
-                // it seems to not contains neither numbers or uppercase...
-                'a'..='z' | '_' => name.push(c),
-                c => panic!(
-                    "Unknown char in Brace item : '{}' at line {}",
-                    c, self.dbg_line
-                ),
+                None => Err(self.error(&format!("`{line}` is not a field")))?,
             }
         }
-        tree
     }
 
     fn insert_item(&mut self, thir: &Thir) {
+        let text = |fields: &HashMap<String, Thir>, name: &str| match fields.get(name) {
+            Some(Thir::Text(text)) => Some(text.clone()),
+            _ => None,
+        };
         match thir {
             Thir::Fields((name, pat_fields)) if name == "Pat" => match pat_fields.get("kind") {
                 Some(Thir::Fields((name, binding_fields))) if name == "Binding" => {
-                    println!("INSERT : {:#?}", thir);
-                    let mut fields = HashMap::new();
-                    fields.extend(pat_fields);
-                    fields.extend(binding_fields);
-                    // let binding =
-                    //     instance_from_thir_raw_string!(PatBinding: fields => name, ty, span);
-                    let binding = instance_from_thir_raw_string!(PatBinding: fields => name, ty);
-                    self.bindings.push(binding);
+                    if let (Some(name), Some(ty)) =
+                        (text(binding_fields, "name"), text(binding_fields, "ty"))
+                    {
+                        // Lifetimes are erased, `&'{erased} str` is written `&str`
+                        let ty = ty.replace("'{erased} ", "");
+                        self.types.bindings.push(PatBinding { name, ty });
+                    }
                 }
                 _ => (),
             },
+            Thir::Fields((name, expr_fields)) if name == "Expr" => {
+                if let (Some(ty), Some(span)) = (text(expr_fields, "ty"), text(expr_fields, "span"))
+                {
+                    self.types.exprs.push(ExprType {
+                        ty: ty.replace("'{erased} ", ""),
+                        span: span.as_str().into(),
+                    });
+                }
+            }
             _ => (),
         }
     }
 }
 
-fn format_main_code(code: String) -> String {
-    format!(
-        r#"
-        fn main() {{
-            {code}
-        }}
-        "#
-    )
+fn is_close(line: &str, close: char) -> bool {
+    let mut chars = line.strip_suffix(',').unwrap_or(line).chars();
+    chars.next() == Some(close) && chars.next().is_none()
 }
 
-fn get_pat_bindings_wrap(
-    code: String,
-    file: OsString,
-    code_name: String,
-) -> Result<Vec<PatBinding>, String> {
-    let main_code = format_main_code(code);
-    let thir_raw = generate_thir(main_code.as_str(), file, code_name);
-    let raw_info = RawInfo::serialize(thir_raw)?;
-    Ok(raw_info.bindings)
+fn split_bodies(thir_raw: &str) -> HashMap<String, String> {
+    //! THIR of each function, by name. A body starts with `DefId(0:3 ~ crate[hash]::name):`.
+    let mut bodies: HashMap<String, String> = HashMap::new();
+    let mut current = None;
+    for line in thir_raw.lines() {
+        if line.starts_with("DefId(") && line.ends_with("):") {
+            let name = line
+                .trim_end_matches("):")
+                .rsplit("::")
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            current = Some(name.clone());
+            bodies.insert(name, String::new());
+        }
+        if let Some(body) = current.as_ref().and_then(|name| bodies.get_mut(name)) {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+    bodies
 }
 
-pub fn get_pat_bindings(code: String, file: OsString, code_name: String) -> Vec<PatBinding> {
-    match get_pat_bindings_wrap(code, file.clone(), code_name.clone()) {
-        Ok(bindings) => bindings,
-        Err(error) => {
-            println!("{}", error);
-            panic!(
-                "Failed to parse THIR {code_name} in {}.",
-                file.to_str().unwrap_or("{unknown}")
-            );
+fn batch_code(declarations: &str, codes: &[String]) -> (String, Vec<u32>) {
+    //! Each code is the body of a function, after the declarations of the file's items.
+    //! Return the batched code and the line where each code starts.
+    let mut batch = format!("{declarations}\n");
+    let mut first_lines = Vec::with_capacity(codes.len());
+    for (i, code) in codes.iter().enumerate() {
+        batch.push_str(&format!("fn query_{i}() {{\n"));
+        first_lines.push(batch.lines().count() as u32 + 1);
+        batch.push_str(&format!("{code}\n}}\n"));
+    }
+    batch.push_str("fn main() {}\n");
+    (batch, first_lines)
+}
+
+pub fn get_types_batch(
+    codes: &[(String, proc_macro2::Span)],
+    declarations: &str,
+    file: &str,
+) -> Vec<syn::Result<Types>> {
+    //! Types of each code, found by one rustc invocation. When one code doesn't
+    //! compile, the others are found one by one. Errors are given at the code's span.
+    let (batch, first_lines) = batch_code(
+        declarations,
+        &codes
+            .iter()
+            .map(|(code, _)| code.clone())
+            .collect::<Vec<_>>(),
+    );
+    let bodies = match generate_thir(&batch, file) {
+        Ok(thir_raw) => split_bodies(&thir_raw),
+        Err(error) if codes.len() == 1 => return vec![Err(syn::Error::new(codes[0].1, error))],
+        Err(_) => {
+            return codes
+                .iter()
+                .flat_map(|code| get_types_batch(std::slice::from_ref(code), declarations, file))
+                .collect()
         }
+    };
+    codes
+        .iter()
+        .zip(first_lines)
+        .enumerate()
+        .map(
+            |(i, ((_, span), first_line))| match bodies.get(&format!("query_{i}")) {
+                Some(body) => RawInfo::serialize(body, *span).map(|types| types.rebase(first_line)),
+                None => Err(syn::Error::new(*span, format!("No THIR for query_{i}."))),
+            },
+        )
+        .collect()
+}
+
+pub fn get_types(
+    code: String,
+    span: proc_macro2::Span,
+    declarations: &str,
+    file: &str,
+) -> syn::Result<Types> {
+    get_types_batch(&[(code, span)], declarations, file)
+        .pop()
+        .unwrap_or_else(|| Err(syn::Error::new(span, "No THIR.")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        batch_code, generate_thir, split_bodies, thir_artefacts_dir, thir_hash, Location, RawInfo,
+    };
+    use proc_macro2::Span;
+    use std::fs;
+
+    #[test]
+    fn cached_thir() {
+        let (code, file) = ("fn main() {}", "tests/cached_thir.rs");
+        let dir = thir_artefacts_dir(file);
+        fs::create_dir_all(&dir).unwrap();
+        let hash = thir_hash(code);
+        // Artefacts of another code with the same hash
+        fs::write(dir.join(format!("{hash}.rs")), "fn other() {}").unwrap();
+        fs::write(dir.join(format!("{hash}.error")), "Not this code.").unwrap();
+        let thir = generate_thir(code, file);
+        assert_ne!(thir, Err("Not this code.".to_string()));
+        // The THIR or the failure (ex: a stable rustc) is reused
+        assert!(
+            dir.join(format!("{hash}.txt")).exists() || dir.join(format!("{hash}.error")).exists()
+        );
+        assert_eq!(generate_thir(code, file), thir);
+    }
+
+    #[test]
+    fn batched_bodies() {
+        let (code, first_lines) = batch_code(
+            "struct Todo;",
+            &["let a = 1u8;".to_string(), "let b = 2u16;".to_string()],
+        );
+        assert!(code.starts_with("struct Todo;\n"));
+        assert_eq!(first_lines, [3, 6]);
+        assert_eq!(code.lines().nth(5), Some("let b = 2u16;"));
+        assert!(code.contains("fn query_0() {\nlet a = 1u8;\n}"));
+        assert!(code.contains("fn query_1() {\nlet b = 2u16;\n}"));
+
+        let bodies = split_bodies(
+            "DefId(0:3 ~ code[e65a]::query_0):\nThir {\n}\nDefId(0:4 ~ code[e65a]::query_1):\nThir {\n}\n",
+        );
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies["query_1"].starts_with("DefId(0:4"));
+    }
+
+    #[test]
+    fn parse_types() {
+        let thir = r#"DefId(0:3 ~ code[e65a]::query_0):
+Thir {
+    body_type: Fn(
+        fn(),
+    ),
+    arms: [],
+    exprs: [
+        Expr {
+            kind: Literal {
+                lit: Spanned {
+                    node: Str(
+                        "x",
+                        Cooked,
+                    ),
+                    span: src/lib.rs/0.rs:2:9: 2:12 (#0),
+                },
+                neg: false,
+            },
+            ty: &'{erased} str,
+            temp_scope_id: 3,
+            span: src/lib.rs/0.rs:2:9: 2:12 (#0),
+        },
+    ],
+    stmts: [
+        Stmt {
+            kind: Let {
+                remainder_scope: Remainder { block: 1, first_statement_index: 0},
+                pattern: Pat {
+                    ty: &'{erased} str,
+                    span: src/lib.rs/0.rs:2:5: 2:6 (#0),
+                    extra: None,
+                    kind: Binding {
+                        name: "b",
+                        ty: &'{erased} str,
+                        subpattern: None,
+                        is_primary: true,
+                    },
+                },
+                initializer: Some(
+                    e0,
+                ),
+                span: src/lib.rs/0.rs:2:1: 2:12 (#0),
+            },
+        },
+    ],
+    params: [],
+}
+"#;
+        let types = RawInfo::serialize(thir, Span::call_site()).expect("the THIR is parsed");
+        assert_eq!(types.bindings.len(), 1);
+        assert_eq!(types.bindings[0].name, "b");
+        assert_eq!(types.bindings[0].ty, "&str");
+        let start = Location { line: 2, column: 9 };
+        let end = Location {
+            line: 2,
+            column: 12,
+        };
+        assert_eq!(types.expr_type(start, end), Some("&str"));
+        // Lines of the query's code when it starts at the second line of the batch
+        let types = types.rebase(2);
+        let start = Location { line: 1, column: 9 };
+        let end = Location {
+            line: 1,
+            column: 12,
+        };
+        assert_eq!(types.expr_type(start, end), Some("&str"));
+
+        let error = RawInfo::serialize(
+            "DefId(0:3 ~ code[e65a]::query_0):\nThir {\n    arms: [\n",
+            Span::call_site(),
+        )
+        .expect_err("the THIR is cut");
+        assert!(error.to_string().contains("line 3"));
     }
 }
@@ -0,0 +1,1266 @@
+//! Types resolved from the declared types of the states.
+//!
+//! It works on a stable toolchain and the user's own types are kept as written,
+//! they are checked by the real compiler with the generated code.
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{
+    parse::ParseStream,
+    parse_quote, parse_str,
+    visit_mut::{self, VisitMut},
+    BinOp, Binding, Block, Expr, ExprAsync, ExprAwait, ExprBinary, ExprCall, ExprField, ExprIndex,
+    ExprLit, ExprMacro, ExprMethodCall, ExprParen, ExprPath, ExprRange, ExprReference, ExprStruct,
+    ExprTuple, ExprUnary, FieldPat, Fields, FnArg, GenericArgument, Ident, ImplItem,
+    ImplItemMethod, Item, ItemConst, ItemEnum, ItemFn, ItemImpl, ItemMod, ItemStatic, ItemStruct,
+    ItemTrait, ItemType, ItemUse, Lit, Macro, Member, Meta, MetaList, NestedMeta, Pat, PatIdent,
+    PatOr, PatReference, PatStruct, PatTuple, PatTupleStruct, PatType, Path, PathArguments,
+    Receiver, ReturnType, Signature, Stmt, TraitBound, Type, TypeArray, TypeImplTrait,
+    TypeParamBound, TypeParen, TypePath, TypeReference, TypeSlice, TypeTuple, UnOp, UseGroup,
+    UseName, UsePath, UseRename, UseTree, Variant,
+};
+
+use crate::state_block::State;
+
+pub fn expr_type(expr: &Expr, states: &[State], items: &FileItems) -> Option<Type> {
+    //! Type of an expression, when it is obvious from literals, the read states and the
+    //! fields of the file's structs.
+    match expr {
+        Expr::Lit(ExprLit { lit, .. }) => lit_type(lit),
+        Expr::Path(ExprPath { path, .. }) => match path.get_ident() {
+            Some(ident) => states
+                .iter()
+                .find(|state| &state.ident == ident)
+                .map(|state| state.ty.clone())
+                // `_` is the type of a state not resolved yet
+                .filter(|ty| !matches!(ty, Type::Infer(_))),
+            // A unit variant of the file (ex: `Status::Loading`)
+            None => variant_type(path, items),
+        },
+        Expr::Paren(ExprParen { expr, .. }) => expr_type(expr, states, items),
+        Expr::Field(ExprField { base, member, .. }) => {
+            field_type(&expr_type(base, states, items)?, member, items)
+        }
+        Expr::Index(ExprIndex { expr, index, .. }) if !matches!(**index, Expr::Range(_)) => {
+            index_type(&expr_type(expr, states, items)?)
+        }
+        Expr::Tuple(ExprTuple { elems, .. }) => {
+            let types = elems
+                .iter()
+                .map(|elem| expr_type(elem, states, items))
+                .collect::<Option<Vec<_>>>()?;
+            Some(parse_quote!((#(#types,)*)))
+        }
+        Expr::Reference(ExprReference {
+            expr, mutability, ..
+        }) => {
+            let ty = expr_type(expr, states, items)?;
+            Some(parse_quote!(& #mutability #ty))
+        }
+        Expr::Unary(ExprUnary {
+            op: UnOp::Deref(_),
+            expr,
+            ..
+        }) => match expr_type(expr, states, items)? {
+            Type::Reference(TypeReference { elem, .. }) => Some(*elem),
+            _ => None,
+        },
+        Expr::Unary(ExprUnary {
+            op: UnOp::Not(_),
+            expr,
+            ..
+        })
+        | Expr::Unary(ExprUnary {
+            op: UnOp::Neg(_),
+            expr,
+            ..
+        }) => expr_type(expr, states, items),
+        Expr::Binary(ExprBinary {
+            left, op, right, ..
+        }) => match op {
+            BinOp::Eq(_)
+            | BinOp::Ne(_)
+            | BinOp::Lt(_)
+            | BinOp::Le(_)
+            | BinOp::Gt(_)
+            | BinOp::Ge(_)
+            | BinOp::And(_)
+            | BinOp::Or(_) => Some(parse_quote!(bool)),
+            // The operands of arithmetic are mostly of the same type, a state's one is preferred
+            // to the default type of a literal
+            _ => match **left {
+                Expr::Lit(_) => {
+                    expr_type(right, states, items).or_else(|| expr_type(left, states, items))
+                }
+                _ => expr_type(left, states, items).map(|ty| owned(&ty)),
+            },
+        },
+        Expr::MethodCall(ExprMethodCall {
+            receiver, method, ..
+        }) => match method.to_string().as_str() {
+            "to_string" => Some(parse_quote!(String)),
+            "len" | "count" => Some(parse_quote!(usize)),
+            "is_empty" | "contains" | "starts_with" | "ends_with" => Some(parse_quote!(bool)),
+            "clone" | "to_owned" => match expr_type(receiver, states, items)? {
+                Type::Reference(TypeReference { elem, .. }) if is_str(&elem) => {
+                    Some(parse_quote!(String))
+                }
+                ty => Some(owned(&ty)),
+            },
+            // Methods of the file's types
+            _ => {
+                let ty = deref_type(expr_type(receiver, states, items)?);
+                returned_type(items.find_method(&ty, method)?, Some(&ty))
+            }
+        },
+        Expr::Call(ExprCall { func, args, .. }) => match **func {
+            // `String::from("text")` or `String::new()`
+            Expr::Path(ExprPath { ref path, .. })
+                if path.segments.len() == 2 && path.segments[0].ident == "String" =>
+            {
+                Some(parse_quote!(String))
+            }
+            Expr::Path(ExprPath { ref path, .. }) if path.is_ident("Some") => {
+                let value = expr_type(args.first()?, states, items)?;
+                Some(parse_quote!(Option<#value>))
+            }
+            Expr::Path(ExprPath { ref path, .. }) => called_type(path, items),
+            _ => None,
+        },
+        Expr::Macro(ExprMacro { mac, .. }) => macro_type(mac, states, items),
+        Expr::Await(ExprAwait { base, .. }) => future_output_type(base, states, items),
+        Expr::Struct(ExprStruct { path, .. })
+            if path.segments.iter().all(|s| s.arguments.is_empty()) =>
+        {
+            Some(Type::Path(TypePath {
+                qself: None,
+                path: path.clone(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+pub fn future_output_type(future: &Expr, states: &[State], items: &FileItems) -> Option<Type> {
+    //! Output of an awaited future : of an async block, or of a call to an async function
+    //! of the file (or returning `impl Future<Output = T>`).
+    match future {
+        Expr::Paren(ExprParen { expr, .. }) => future_output_type(expr, states, items),
+        Expr::Async(ExprAsync { block, .. }) => match block.stmts.last() {
+            Some(Stmt::Expr(expr)) => expr_type(expr, states, items),
+            _ => Some(parse_quote!(())),
+        },
+        Expr::Call(ExprCall { func, .. }) => match &**func {
+            Expr::Path(ExprPath { path, .. }) => {
+                signature_output(items.find_fn(path)?, path_prefix_type(path).as_ref())
+            }
+            _ => None,
+        },
+        // Async methods of the file's types (ex: `api.load()`)
+        Expr::MethodCall(ExprMethodCall {
+            receiver, method, ..
+        }) => {
+            let ty = deref_type(expr_type(receiver, states, items)?);
+            signature_output(items.find_method(&ty, method)?, Some(&ty))
+        }
+        _ => None,
+    }
+}
+
+fn signature_output(signature: &Signature, self_ty: Option<&Type>) -> Option<Type> {
+    //! Output of the future returned by an async function (or returning `impl Future`).
+    let output = match (&signature.asyncness, &signature.output) {
+        (Some(_), ReturnType::Default) => parse_quote!(()),
+        (Some(_), ReturnType::Type(_, ty)) => (**ty).clone(),
+        (None, ReturnType::Type(_, ty)) => impl_future_output(ty)?,
+        (None, ReturnType::Default) => None?,
+    };
+    with_self_type(output, self_ty)
+}
+
+fn returned_type(signature: &Signature, self_ty: Option<&Type>) -> Option<Type> {
+    //! Type returned by a call of a function of the file, `Self` is the type of its impl.
+    //! Async and generic functions aren't typed.
+    if signature.asyncness.is_some() || signature.generics.type_params().next().is_some() {
+        return None;
+    }
+    match &signature.output {
+        ReturnType::Default => Some(parse_quote!(())),
+        ReturnType::Type(_, ty) => with_self_type((**ty).clone(), self_ty),
+    }
+}
+
+fn called_type(path: &Path, items: &FileItems) -> Option<Type> {
+    //! Type of a call : returned by a function of the file (ex: `load()` or `Todo::new()`),
+    //! or built by one of its tuple structs or variants (ex: `Status::Loaded(..)`).
+    if let Some(signature) = items.find_fn(path) {
+        return returned_type(signature, path_prefix_type(path).as_ref());
+    }
+    let ty = Type::Path(TypePath {
+        qself: None,
+        path: path.clone(),
+    });
+    match items.find_struct(&ty) {
+        Some(item) if matches!(item.fields, Fields::Unnamed(_)) => Some(ty),
+        _ => variant_type(path, items),
+    }
+}
+
+fn variant_type(path: &Path, items: &FileItems) -> Option<Type> {
+    //! Enum of the file having this variant (ex: `Status::Loading`).
+    let ty = path_prefix_type(path)?;
+    items.find_variant(&ty, path).map(|_| ty)
+}
+
+fn path_prefix_type(path: &Path) -> Option<Type> {
+    //! `Todo` of `Todo::new`.
+    let count = path
+        .segments
+        .len()
+        .checked_sub(1)
+        .filter(|&count| count > 0)?;
+    let segments = path.segments.iter().take(count).cloned().collect();
+    Some(Type::Path(TypePath {
+        qself: None,
+        path: Path {
+            leading_colon: path.leading_colon,
+            segments,
+        },
+    }))
+}
+
+fn macro_type(mac: &Macro, states: &[State], items: &FileItems) -> Option<Type> {
+    //! `format!(..)` is a `String`, `vec![..]` a `Vec` of its first element's type.
+    match mac.path.segments.last()?.ident.to_string().as_str() {
+        "format" => Some(parse_quote!(String)),
+        "vec" => {
+            let first = mac
+                .parse_body_with(|input: ParseStream| {
+                    let first: Expr = input.parse()?;
+                    input.parse::<TokenStream>()?;
+                    Ok(first)
+                })
+                .ok()?;
+            let item = expr_type(&first, states, items)?;
+            Some(parse_quote!(Vec<#item>))
+        }
+        _ => None,
+    }
+}
+
+fn with_self_type(mut ty: Type, self_ty: Option<&Type>) -> Option<Type> {
+    //! Replace `Self` by the type of the impl, `impl Trait` types aren't known.
+    let mut replacer = SelfReplacer {
+        self_ty,
+        unknown: false,
+    };
+    replacer.visit_type_mut(&mut ty);
+    (!replacer.unknown).then_some(ty)
+}
+
+struct SelfReplacer<'a> {
+    self_ty: Option<&'a Type>,
+    /// `Self` out of an impl, or an `impl Trait`
+    unknown: bool,
+}
+
+impl VisitMut for SelfReplacer<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        match ty {
+            Type::Path(TypePath { qself: None, path }) if path.is_ident("Self") => {
+                match self.self_ty {
+                    Some(self_ty) => *ty = self_ty.clone(),
+                    None => self.unknown = true,
+                }
+            }
+            Type::ImplTrait(_) => self.unknown = true,
+            _ => visit_mut::visit_type_mut(self, ty),
+        }
+    }
+}
+
+fn impl_future_output(ty: &Type) -> Option<Type> {
+    //! `T` of `impl Future<Output = T>`.
+    let bounds = match ty {
+        Type::ImplTrait(TypeImplTrait { bounds, .. }) => bounds,
+        _ => None?,
+    };
+    bounds.iter().find_map(|bound| {
+        let segment = match bound {
+            TypeParamBound::Trait(TraitBound { path, .. }) => path.segments.last()?,
+            _ => None?,
+        };
+        match &segment.arguments {
+            PathArguments::AngleBracketed(arguments) if segment.ident == "Future" => {
+                arguments.args.iter().find_map(|argument| match argument {
+                    GenericArgument::Binding(Binding { ident, ty, .. }) if ident == "Output" => {
+                        Some(ty.clone())
+                    }
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    })
+}
+
+pub fn iter_item_type(expr: &Expr, states: &[State], items: &FileItems) -> Option<Type> {
+    //! Type of the items of an iterated expression, as in `for item in expr`.
+    match expr {
+        Expr::Paren(ExprParen { expr, .. }) => iter_item_type(expr, states, items),
+        Expr::Range(ExprRange { from, to, .. }) => {
+            let bound = match (from, to) {
+                (Some(start), Some(end)) => match **start {
+                    Expr::Lit(_) => {
+                        expr_type(end, states, items).or_else(|| expr_type(start, states, items))
+                    }
+                    _ => expr_type(start, states, items),
+                },
+                (Some(bound), None) | (None, Some(bound)) => expr_type(bound, states, items),
+                (None, None) => None,
+            }?;
+            Some(owned(&bound))
+        }
+        Expr::MethodCall(ExprMethodCall {
+            receiver,
+            method,
+            args,
+            ..
+        }) => match method.to_string().as_str() {
+            "iter" => {
+                let ty = expr_type(receiver, states, items)?;
+                let item = into_item_type(&owned(&ty))?;
+                Some(parse_quote!(&#item))
+            }
+            "iter_mut" => {
+                let ty = expr_type(receiver, states, items)?;
+                let item = into_item_type(&owned(&ty))?;
+                Some(parse_quote!(&mut #item))
+            }
+            "into_iter" => into_item_type(&expr_type(receiver, states, items)?),
+            "keys" => {
+                let (key, _) = map_types(&owned(&expr_type(receiver, states, items)?))?;
+                Some(parse_quote!(&#key))
+            }
+            "values" => {
+                let (_, value) = map_types(&owned(&expr_type(receiver, states, items)?))?;
+                Some(parse_quote!(&#value))
+            }
+            "chars" => Some(parse_quote!(char)),
+            "bytes" => Some(parse_quote!(u8)),
+            "enumerate" => {
+                let item = iter_item_type(receiver, states, items)?;
+                Some(parse_quote!((usize, #item)))
+            }
+            "zip" => {
+                let item = iter_item_type(receiver, states, items)?;
+                let other = iter_item_type(args.first()?, states, items)?;
+                Some(parse_quote!((#item, #other)))
+            }
+            "cloned" | "copied" => Some(owned(&iter_item_type(receiver, states, items)?)),
+            "rev" | "skip" | "take" | "step_by" | "cycle" | "chain" | "filter" | "skip_while"
+            | "take_while" | "peekable" | "fuse" => iter_item_type(receiver, states, items),
+            _ => None,
+        },
+        _ => into_item_type(&expr_type(expr, states, items)?),
+    }
+}
+
+pub fn into_item_type(ty: &Type) -> Option<Type> {
+    //! `<ty as IntoIterator>::Item` for the collections of std.
+    match ty {
+        Type::Paren(paren) => into_item_type(&paren.elem),
+        Type::Reference(TypeReference {
+            elem, mutability, ..
+        }) => match map_types(elem) {
+            Some((key, value)) => Some(parse_quote!((&#key, & #mutability #value))),
+            None => {
+                let item = match &**elem {
+                    Type::Slice(TypeSlice { elem, .. }) => (**elem).clone(),
+                    elem => into_item_type(elem)?,
+                };
+                Some(parse_quote!(& #mutability #item))
+            }
+        },
+        Type::Array(TypeArray { elem, .. }) | Type::Slice(TypeSlice { elem, .. }) => {
+            Some((**elem).clone())
+        }
+        Type::Path(_) => match map_types(ty) {
+            Some((key, value)) => Some(parse_quote!((#key, #value))),
+            None => {
+                let (name, generics) = last_segment(ty)?;
+                match (name.as_str(), generics.as_slice()) {
+                    (
+                        "Vec" | "VecDeque" | "LinkedList" | "HashSet" | "BTreeSet" | "BinaryHeap"
+                        | "Option" | "Range" | "RangeInclusive",
+                        [item],
+                    ) => Some(item.clone()),
+                    _ => None,
+                }
+            }
+        },
+        _ => None,
+    }
+}
+
+pub fn pat_bindings(pat: &Pat, ty: &Type, items: &FileItems) -> Option<Vec<(Ident, Type)>> {
+    //! Type of each binding of a pattern matching a value of type `ty`, the fields of
+    //! struct and variant patterns are typed from the definitions of the component's file.
+    match (pat, ty) {
+        (_, Type::Paren(paren)) => pat_bindings(pat, &paren.elem, items),
+        // `Loading` is a unit variant of the matched enum, not a binding
+        (
+            Pat::Ident(PatIdent {
+                ident,
+                subpat: None,
+                ..
+            }),
+            _,
+        ) if is_unit_variant(ident, ty, items) => Some(Vec::new()),
+        (Pat::Ident(PatIdent { ident, subpat, .. }), _) => {
+            let mut bindings = vec![(ident.clone(), ty.clone())];
+            if let Some((_, subpat)) = subpat {
+                bindings.extend(pat_bindings(subpat, ty, items)?);
+            }
+            Some(bindings)
+        }
+        (Pat::Wild(_) | Pat::Rest(_) | Pat::Path(_) | Pat::Lit(_) | Pat::Range(_), _) => {
+            Some(Vec::new())
+        }
+        // Every alternative binds the same names with the same types
+        (Pat::Or(PatOr { cases, .. }), _) => pat_bindings(cases.first()?, ty, items),
+        (Pat::Type(PatType { pat, ty, .. }), _) => pat_bindings(pat, ty, items),
+        (Pat::Reference(PatReference { pat, .. }), Type::Reference(TypeReference { elem, .. })) => {
+            pat_bindings(pat, elem, items)
+        }
+        (Pat::Tuple(PatTuple { elems, .. }), Type::Tuple(TypeTuple { elems: types, .. })) => {
+            elems_bindings(elems.iter(), types.iter(), items)
+        }
+        (Pat::Struct(PatStruct { path, fields, .. }), Type::Path(_)) => {
+            let item_fields = pattern_fields(path, ty, items)?;
+            let mut bindings = Vec::new();
+            for FieldPat { member, pat, .. } in fields {
+                let field = match (member, &item_fields) {
+                    (Member::Named(name), Fields::Named(named)) => named
+                        .named
+                        .iter()
+                        .find(|field| field.ident.as_ref() == Some(name))?,
+                    (Member::Unnamed(index), Fields::Unnamed(unnamed)) => {
+                        unnamed.unnamed.iter().nth(index.index as usize)?
+                    }
+                    _ => None?,
+                };
+                bindings.extend(pat_bindings(pat, &field.ty, items)?);
+            }
+            Some(bindings)
+        }
+        (Pat::TupleStruct(PatTupleStruct { path, pat, .. }), Type::Path(_)) => {
+            match pattern_fields(path, ty, items)? {
+                Fields::Unnamed(unnamed) => elems_bindings(
+                    pat.elems.iter(),
+                    unnamed.unnamed.iter().map(|field| &field.ty),
+                    items,
+                ),
+                _ => None,
+            }
+        }
+        // Match ergonomics, the bindings of a pattern through a reference are references
+        (
+            Pat::Tuple(_) | Pat::Struct(_) | Pat::TupleStruct(_),
+            Type::Reference(TypeReference { elem, .. }),
+        ) => Some(
+            pat_bindings(pat, elem, items)?
+                .into_iter()
+                .map(|(ident, ty)| match ty {
+                    Type::Reference(_) => (ident, ty),
+                    ty => (ident, parse_quote!(&#ty)),
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn elems_bindings<'a>(
+    pats: impl ExactSizeIterator<Item = &'a Pat>,
+    types: impl ExactSizeIterator<Item = &'a Type>,
+    items: &FileItems,
+) -> Option<Vec<(Ident, Type)>> {
+    //! Bindings of the elements of a tuple pattern, `..` isn't supported.
+    if pats.len() != types.len() {
+        return None;
+    }
+    let mut bindings = Vec::new();
+    for (pat, ty) in pats.zip(types) {
+        bindings.extend(pat_bindings(pat, ty, items)?);
+    }
+    Some(bindings)
+}
+
+fn pattern_fields(path: &Path, ty: &Type, items: &FileItems) -> Option<Fields> {
+    //! Fields matched by a struct or a variant pattern : of a struct or an enum of the
+    //! file, or of `Some`, `Ok` and `Err`.
+    let pattern_name = &path.segments.last()?.ident;
+    if let Some(item) = items.find_struct(ty) {
+        return (*pattern_name == item.ident || pattern_name == "Self")
+            .then(|| item.fields.clone());
+    }
+    let (name, generics) = last_segment(ty)?;
+    match (name.as_str(), generics.as_slice()) {
+        ("Option", [value]) if pattern_name == "Some" => {
+            Some(Fields::Unnamed(parse_quote!((#value))))
+        }
+        ("Result", [value, _]) if pattern_name == "Ok" => {
+            Some(Fields::Unnamed(parse_quote!((#value))))
+        }
+        ("Result", [_, error]) if pattern_name == "Err" => {
+            Some(Fields::Unnamed(parse_quote!((#error))))
+        }
+        _ => items
+            .find_variant(ty, path)
+            .map(|variant| variant.fields.clone()),
+    }
+}
+
+fn is_unit_variant(ident: &Ident, ty: &Type, items: &FileItems) -> bool {
+    let ty = deref_type(ty.clone());
+    match last_segment(&ty) {
+        Some((name, _)) if name == "Option" => ident == "None",
+        _ => items
+            .find_variant(&ty, &ident.clone().into())
+            .is_some_and(|variant| matches!(variant.fields, Fields::Unit)),
+    }
+}
+
+/// Items of the component's file, its inline modules included. They type the fields
+/// and the methods of the user's own types.
+#[derive(Debug, Default, Clone)]
+pub struct FileItems {
+    pub structs: Vec<ItemStruct>,
+    pub enums: Vec<ItemEnum>,
+    pub impls: Vec<ItemImpl>,
+    pub fns: Vec<ItemFn>,
+    /// Code declaring the items to rustc before the type queries, see `declarations`.
+    pub declarations: String,
+}
+
+impl FileItems {
+    pub fn from_items(items: &[Item]) -> Self {
+        let mut file_items = Self::default();
+        file_items.collect(items);
+        file_items.declarations = declarations(items)
+            .into_iter()
+            .map(|item| item.into_token_stream())
+            .map(declared_tokens)
+            .collect::<TokenStream>()
+            .to_string();
+        file_items
+    }
+
+    fn collect(&mut self, items: &[Item]) {
+        for item in items {
+            match item {
+                Item::Mod(ItemMod {
+                    content: Some((_, items)),
+                    ..
+                }) => self.collect(items),
+                Item::Struct(item) => self.structs.push(item.clone()),
+                Item::Enum(item) => self.enums.push(item.clone()),
+                Item::Impl(item) => self.impls.push(item.clone()),
+                Item::Fn(item) => self.fns.push(item.clone()),
+                _ => (),
+            }
+        }
+    }
+
+    fn find_struct(&self, ty: &Type) -> Option<&ItemStruct> {
+        //! Definition of a struct type, generic ones aren't supported.
+        let (name, _) = last_segment(ty)?;
+        self.structs
+            .iter()
+            .find(|item| item.ident == name && item.generics.params.is_empty())
+    }
+
+    fn find_fn(&self, path: &Path) -> Option<&Signature> {
+        //! Signature of a function of the file (ex: `load(..)`) or of an associated function
+        //! of its types (ex: `Todo::load(..)`).
+        let mut segments = path.segments.iter().rev();
+        let name = &segments.next()?.ident;
+        match segments.next() {
+            None => self
+                .fns
+                .iter()
+                .map(|item| &item.sig)
+                .find(|signature| signature.ident == *name),
+            Some(self_ty) => self
+                .impls
+                .iter()
+                .filter(|item| {
+                    last_segment(&item.self_ty).is_some_and(|(name, _)| self_ty.ident == name)
+                })
+                .flat_map(|item| &item.items)
+                .find_map(|item| match item {
+                    ImplItem::Method(ImplItemMethod { sig, .. }) if sig.ident == *name => Some(sig),
+                    _ => None,
+                }),
+        }
+    }
+
+    fn find_method(&self, ty: &Type, method: &Ident) -> Option<&Signature> {
+        //! Method of a type of the file (ex: `todo.label()`), generic impls aren't supported.
+        let (name, _) = last_segment(ty)?;
+        self.impls
+            .iter()
+            .filter(|item| {
+                item.generics.params.is_empty()
+                    && last_segment(&item.self_ty).is_some_and(|(self_ty, _)| self_ty == name)
+            })
+            .flat_map(|item| &item.items)
+            .find_map(|item| match item {
+                ImplItem::Method(ImplItemMethod { sig, .. }) if sig.ident == *method => Some(sig),
+                _ => None,
+            })
+    }
+
+    fn find_variant(&self, ty: &Type, path: &Path) -> Option<&Variant> {
+        //! Variant of an enum type named by a path (ex: `Status::Loaded`, or `Loaded` once
+        //! imported), generic enums aren't supported.
+        let (name, _) = last_segment(ty)?;
+        let item = self
+            .enums
+            .iter()
+            .find(|item| item.ident == name && item.generics.params.is_empty())?;
+        let mut segments = path.segments.iter().rev();
+        let variant = &segments.next()?.ident;
+        match segments.next() {
+            Some(segment) if segment.ident != item.ident && segment.ident != "Self" => None,
+            _ => item.variants.iter().find(|item| item.ident == *variant),
+        }
+    }
+}
+
+pub fn file_items(file: &str) -> FileItems {
+    match std::fs::read_to_string(file).map(|code| syn::parse_file(&code)) {
+        Ok(Ok(file)) => FileItems::from_items(&file.items),
+        _ => FileItems::default(),
+    }
+}
+
+/// Attributes kept on the declarations, the others may come from crates rustc doesn't see.
+const DECLARED_ATTRIBUTES: &[&str] = &["allow", "cfg", "default", "derive", "repr"];
+
+/// Derives of std, the other ones are removed from the declarations.
+const STD_DERIVES: &[&str] = &[
+    "Clone",
+    "Copy",
+    "Debug",
+    "Default",
+    "Eq",
+    "Hash",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+];
+
+fn declarations(items: &[Item]) -> Vec<Item> {
+    //! Items given to rustc with the type queries, so the states can have the user's types.
+    //! Functions are declared without their bodies, imports of other crates and macros
+    //! are removed.
+    let mut names = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Const(ItemConst { ident, .. })
+            | Item::Enum(ItemEnum { ident, .. })
+            | Item::Mod(ItemMod { ident, .. })
+            | Item::Static(ItemStatic { ident, .. })
+            | Item::Struct(ItemStruct { ident, .. })
+            | Item::Trait(ItemTrait { ident, .. })
+            | Item::Type(ItemType { ident, .. }) => Some(ident),
+            Item::Fn(ItemFn { sig, .. }) => Some(&sig.ident),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // Names imported from the file's items are local too (ex: `use Status::*` after
+    // `use models::Status`), until no import adds one
+    loop {
+        let imported = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Use(ItemUse { tree, .. }) if is_local_use(tree, &names) => Some(tree),
+                _ => None,
+            })
+            .flat_map(imported_names)
+            .filter(|ident| !names.contains(ident))
+            .collect::<Vec<_>>();
+        if imported.is_empty() {
+            break;
+        }
+        names.extend(imported);
+    }
+    items
+        .iter()
+        .filter_map(|item| {
+            let mut item = match item {
+                Item::Mod(
+                    item_mod @ ItemMod {
+                        content: Some((brace, items)),
+                        ..
+                    },
+                ) => {
+                    let mut item_mod = item_mod.clone();
+                    item_mod.content = Some((*brace, declarations(items)));
+                    return Some(Item::Mod(item_mod));
+                }
+                Item::Const(_)
+                | Item::Enum(_)
+                | Item::Impl(_)
+                | Item::Static(_)
+                | Item::Struct(_)
+                | Item::Trait(_)
+                | Item::Type(_) => item.clone(),
+                // The queries are declared with their own `main`
+                Item::Fn(ItemFn { sig, .. }) if sig.ident != "main" => item.clone(),
+                Item::Use(ItemUse { tree, .. }) if is_local_use(tree, &names) => item.clone(),
+                _ => return None,
+            };
+            BodyRemover.visit_item_mut(&mut item);
+            Some(item)
+        })
+        .collect()
+}
+
+fn is_local_use(tree: &UseTree, names: &[&Ident]) -> bool {
+    //! If the import is of std or of the file's items (ex: `crate::Todo` or `Status::*`).
+    match tree {
+        UseTree::Path(UsePath { ident, tree, .. }) if ident == "crate" => is_local_use(tree, names),
+        UseTree::Path(UsePath { ident, .. })
+        | UseTree::Name(UseName { ident })
+        | UseTree::Rename(UseRename { ident, .. }) => {
+            ["std", "core", "alloc", "self", "super"].contains(&ident.to_string().as_str())
+                || names.contains(&ident)
+        }
+        UseTree::Group(UseGroup { items, .. }) => {
+            items.iter().all(|tree| is_local_use(tree, names))
+        }
+        // Its path is already checked
+        UseTree::Glob(_) => true,
+    }
+}
+
+fn imported_names(tree: &UseTree) -> Vec<&Ident> {
+    match tree {
+        UseTree::Path(UsePath { tree, .. }) => imported_names(tree),
+        UseTree::Name(UseName { ident }) | UseTree::Rename(UseRename { rename: ident, .. }) => {
+            vec![ident]
+        }
+        UseTree::Group(UseGroup { items, .. }) => items.iter().flat_map(imported_names).collect(),
+        UseTree::Glob(_) => Vec::new(),
+    }
+}
+
+/// Replaces the bodies of the functions by `unimplemented!()`, only their signatures are
+/// needed to type the queries.
+struct BodyRemover;
+
+impl VisitMut for BodyRemover {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        *block = parse_quote!({ unimplemented!() });
+    }
+
+    fn visit_expr_mut(&mut self, _: &mut Expr) {
+        // Constants keep their value
+    }
+}
+
+fn declared_tokens(tokens: TokenStream) -> TokenStream {
+    //! Tokens of a declaration without the attributes rustc may not know.
+    let mut declared = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == '#' => {
+                let attribute = match tokens.peek() {
+                    Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => {
+                        group.stream()
+                    }
+                    _ => {
+                        declared.push(TokenTree::Punct(punct));
+                        continue;
+                    }
+                };
+                tokens.next();
+                if let Some(attribute) = declared_attribute(attribute) {
+                    declared.push(TokenTree::Punct(punct));
+                    declared.push(TokenTree::Group(Group::new(Delimiter::Bracket, attribute)));
+                }
+            }
+            TokenTree::Group(group) => {
+                let mut declared_group =
+                    Group::new(group.delimiter(), declared_tokens(group.stream()));
+                declared_group.set_span(group.span());
+                declared.push(TokenTree::Group(declared_group));
+            }
+            token => declared.push(token),
+        }
+    }
+    declared.into_iter().collect()
+}
+
+fn declared_attribute(attribute: TokenStream) -> Option<TokenStream> {
+    //! `#[derive(..)]` keeps the derives of std, other attributes are kept when rustc knows
+    //! them.
+    match syn::parse2::<Meta>(attribute.clone()).ok()? {
+        Meta::List(MetaList { path, nested, .. }) if path.is_ident("derive") => {
+            let derives = nested
+                .into_iter()
+                .filter(|derive| match derive {
+                    NestedMeta::Meta(Meta::Path(path)) => path
+                        .get_ident()
+                        .is_some_and(|ident| STD_DERIVES.contains(&ident.to_string().as_str())),
+                    _ => false,
+                })
+                .collect::<Vec<_>>();
+            (!derives.is_empty()).then(|| quote!(derive(#(#derives),*)))
+        }
+        meta if DECLARED_ATTRIBUTES
+            .iter()
+            .any(|name| meta.path().is_ident(name)) =>
+        {
+            Some(attribute)
+        }
+        _ => None,
+    }
+}
+
+/// Methods of the std traits taking `&self`, once the receiver's type is known they don't
+/// write it (unless the file's impls say otherwise).
+const TRAIT_METHODS: &[&str] = &[
+    "as_ref",
+    "clone",
+    "cmp",
+    "eq",
+    "fmt",
+    "hash",
+    "ne",
+    "partial_cmp",
+    "to_owned",
+    "to_string",
+];
+
+/// Mutability of the receivers of the methods called on the states. The types are read
+/// from the states, the methods from the impl blocks of the component's file or from
+/// the std types. A method of an unknown type may take `&mut self`.
+#[derive(Default)]
+pub struct Receivers {
+    states: Vec<State>,
+    items: FileItems,
+}
+
+impl Receivers {
+    pub fn new(states: &[State], items: &FileItems) -> Self {
+        Self {
+            states: states.to_vec(),
+            items: items.clone(),
+        }
+    }
+
+    pub fn is_mutable(&self, receiver: &Expr, method: &Ident) -> bool {
+        //! If the method may take `&mut self` on this receiver (ex: `items.push(..)`).
+        let method = method.to_string();
+        expr_type(receiver, &self.states, &self.items)
+            .and_then(|ty| self.method_mutability(&ty, &method))
+            .unwrap_or(true)
+    }
+
+    fn method_mutability(&self, ty: &Type, method: &str) -> Option<bool> {
+        let ty = deref_type(ty.clone());
+        let name = match &ty {
+            Type::Array(_) | Type::Slice(_) => "Vec".to_string(),
+            ty => last_segment(ty)?.0,
+        };
+        // Methods of the file's types, by their signature
+        let signature = self
+            .items
+            .impls
+            .iter()
+            .filter(|item| last_segment(&item.self_ty).is_some_and(|(self_ty, _)| self_ty == name))
+            .flat_map(|item| &item.items)
+            .find_map(|item| match item {
+                ImplItem::Method(ImplItemMethod { sig, .. }) if sig.ident == method => Some(sig),
+                _ => None,
+            });
+        if let Some(signature) = signature {
+            return match signature.inputs.first() {
+                Some(FnArg::Receiver(Receiver {
+                    reference: Some(_),
+                    mutability,
+                    ..
+                })) => Some(mutability.is_some()),
+                // Taken by value, the state isn't written (or it can't be moved out)
+                Some(FnArg::Receiver(_)) => Some(false),
+                _ => None,
+            };
+        }
+        if TRAIT_METHODS.contains(&method) {
+            return Some(false);
+        }
+        // Methods of the std types taking `&self` or `self`
+        let shared: &[&str] = match name.as_str() {
+            "bool" | "char" | "f32" | "f64" | "i8" | "i16" | "i32" | "i64" | "i128" | "isize"
+            | "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => return Some(false),
+            "Vec" | "VecDeque" => &[
+                "as_slice",
+                "back",
+                "binary_search",
+                "capacity",
+                "chunks",
+                "concat",
+                "contains",
+                "ends_with",
+                "first",
+                "front",
+                "get",
+                "is_empty",
+                "iter",
+                "join",
+                "last",
+                "len",
+                "starts_with",
+                "to_vec",
+                "windows",
+            ],
+            "String" | "str" => &[
+                "as_bytes",
+                "as_str",
+                "bytes",
+                "capacity",
+                "char_indices",
+                "chars",
+                "contains",
+                "ends_with",
+                "find",
+                "is_empty",
+                "len",
+                "lines",
+                "parse",
+                "repeat",
+                "replace",
+                "split",
+                "split_whitespace",
+                "starts_with",
+                "to_lowercase",
+                "to_uppercase",
+                "trim",
+                "trim_end",
+                "trim_start",
+            ],
+            "HashMap" | "BTreeMap" => &[
+                "contains_key",
+                "get",
+                "is_empty",
+                "iter",
+                "keys",
+                "len",
+                "values",
+            ],
+            "HashSet" | "BTreeSet" => &[
+                "contains",
+                "difference",
+                "get",
+                "intersection",
+                "is_disjoint",
+                "is_empty",
+                "is_subset",
+                "is_superset",
+                "iter",
+                "len",
+                "symmetric_difference",
+                "union",
+            ],
+            "Option" | "Result" => &[
+                "and_then",
+                "as_deref",
+                "as_ref",
+                "cloned",
+                "copied",
+                "err",
+                "expect",
+                "filter",
+                "is_err",
+                "is_none",
+                "is_ok",
+                "is_some",
+                "iter",
+                "map",
+                "ok",
+                "ok_or",
+                "unwrap",
+                "unwrap_or",
+                "unwrap_or_default",
+                "unwrap_or_else",
+            ],
+            _ => return None,
+        };
+        Some(!shared.contains(&method))
+    }
+}
+
+fn field_type(ty: &Type, member: &Member, items: &FileItems) -> Option<Type> {
+    //! Type of a field of a tuple or of a struct of the file, reached by auto-deref.
+    match (deref_type(ty.clone()), member) {
+        (Type::Tuple(TypeTuple { elems, .. }), Member::Unnamed(index)) => {
+            elems.iter().nth(index.index as usize).cloned()
+        }
+        (ty, member) => {
+            let item = items.find_struct(&ty)?;
+            let field = match member {
+                Member::Named(name) => item
+                    .fields
+                    .iter()
+                    .find(|field| field.ident.as_ref() == Some(name)),
+                Member::Unnamed(index) => item.fields.iter().nth(index.index as usize),
+            };
+            field.map(|field| field.ty.clone())
+        }
+    }
+}
+
+fn index_type(ty: &Type) -> Option<Type> {
+    //! Type of an element of an indexed collection, ex: `items[0]`.
+    match deref_type(ty.clone()) {
+        Type::Array(TypeArray { elem, .. }) | Type::Slice(TypeSlice { elem, .. }) => Some(*elem),
+        ty => match last_segment(&ty)? {
+            (name, generics) if name == "Vec" || name == "VecDeque" => generics.first().cloned(),
+            (name, generics) if name == "HashMap" || name == "BTreeMap" => generics.get(1).cloned(),
+            _ => None,
+        },
+    }
+}
+
+fn deref_type(ty: Type) -> Type {
+    //! Type of the value reached by auto-deref, through references and boxes.
+    match ty {
+        Type::Reference(TypeReference { elem, .. }) | Type::Paren(TypeParen { elem, .. }) => {
+            deref_type(*elem)
+        }
+        ty => match last_segment(&ty) {
+            Some((name, generics)) if name == "Box" && generics.len() == 1 => {
+                deref_type(generics[0].clone())
+            }
+            _ => ty,
+        },
+    }
+}
+
+pub fn owned(ty: &Type) -> Type {
+    //! Type of a reference's clone.
+    match ty {
+        Type::Reference(TypeReference { elem, .. }) if is_str(elem) => parse_quote!(String),
+        Type::Reference(TypeReference { elem, .. }) => (**elem).clone(),
+        ty => ty.clone(),
+    }
+}
+
+fn lit_type(lit: &Lit) -> Option<Type> {
+    match lit {
+        Lit::Int(int) if int.suffix().is_empty() => Some(parse_quote!(i32)),
+        Lit::Float(float) if float.suffix().is_empty() => Some(parse_quote!(f64)),
+        Lit::Int(int) => parse_str(int.suffix()).ok(),
+        Lit::Float(float) => parse_str(float.suffix()).ok(),
+        Lit::Bool(_) => Some(parse_quote!(bool)),
+        Lit::Char(_) => Some(parse_quote!(char)),
+        Lit::Str(_) => Some(parse_quote!(&'static str)),
+        _ => None,
+    }
+}
+
+fn is_str(ty: &Type) -> bool {
+    matches!(ty, Type::Path(TypePath { path, .. }) if path.is_ident("str"))
+}
+
+fn map_types(ty: &Type) -> Option<(Type, Type)> {
+    let (name, generics) = last_segment(ty)?;
+    match (name.as_str(), generics.as_slice()) {
+        ("HashMap" | "BTreeMap", [key, value]) => Some((key.clone(), value.clone())),
+        _ => None,
+    }
+}
+
+fn last_segment(ty: &Type) -> Option<(String, Vec<Type>)> {
+    //! Name and generic types of a path's last segment.
+    match ty {
+        Type::Path(TypePath { path, .. }) => {
+            let segment = path.segments.last()?;
+            let generics = match &segment.arguments {
+                PathArguments::AngleBracketed(arguments) => arguments
+                    .args
+                    .iter()
+                    .filter_map(|argument| match argument {
+                        GenericArgument::Type(ty) => Some(ty.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            Some((segment.ident.to_string(), generics))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        expr_type, future_output_type, iter_item_type, owned, pat_bindings, FileItems, Receivers,
+    };
+    use crate::state_block::{extract_locals, parse_state_block};
+    use quote::quote;
+    use syn::parse::Parser;
+
+    #[test]
+    fn for_loop_items() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let todos: Vec<Todo> = Vec::new();
+                let count: u32 = 3;
+                let board: Board = Board::default();
+            }",
+        )?;
+        let items = syn::parse_file("struct Board { columns: [Vec<Todo>; 3] }")?;
+        let details = extract_locals(&block, FileItems::from_items(&items.items))?;
+        let item_type = |expr: &str| {
+            let ty = iter_item_type(
+                &syn::parse_str(expr).unwrap(),
+                &details.states,
+                &details.items,
+            )
+            .map(|ty| owned(&ty));
+            quote!(#ty).to_string()
+        };
+        assert_eq!(item_type("todos.iter()"), "Todo");
+        assert_eq!(item_type("0..count"), "u32");
+        assert_eq!(item_type("board.columns[1].iter()"), "Todo");
+        assert_eq!(item_type("todos.iter().map(|todo| todo.id)"), "");
+
+        let item = iter_item_type(
+            &syn::parse_str("todos.iter().enumerate()")?,
+            &details.states,
+            &details.items,
+        )
+        .expect("enumerate is typed");
+        let bindings = pat_bindings(&syn::parse_str("(index, todo)")?, &item, &details.items)
+            .expect("the tuple matches");
+        let bindings = bindings
+            .iter()
+            .map(|(ident, ty)| format!("{ident}: {}", quote!(#ty)))
+            .collect::<Vec<_>>();
+        assert_eq!(bindings, ["index: usize", "todo: & Todo"]);
+
+        let items = syn::parse_file("struct Point { x: i32, label: String } struct Meters(f64);")?;
+        let bindings = pat_bindings(
+            &syn::parse_str("(Point { x, label: name }, Meters(length))")?,
+            &syn::parse_str("&(Point, Meters)")?,
+            &FileItems::from_items(&items.items),
+        )
+        .expect("the structs match");
+        let bindings = bindings
+            .iter()
+            .map(|(ident, ty)| format!("{ident}: {}", quote!(#ty)))
+            .collect::<Vec<_>>();
+        assert_eq!(bindings, ["x: & i32", "name: & String", "length: & f64"]);
+        Ok(())
+    }
+
+    #[test]
+    fn called_types() -> syn::Result<()> {
+        let items = syn::parse_file(
+            "fn compute() -> u32 { 3 }
+            fn parse<T>() -> T { todo!() }
+            struct Meters(f64);
+            enum Status { Loading, Loaded(u32) }
+            struct Todo { label: String }
+            impl Todo {
+                fn new() -> Self { todo!() }
+                fn label(&self) -> &str { &self.label }
+                async fn save(&self) -> Result<Self, String> { todo!() }
+            }",
+        )?;
+        let block = parse_state_block.parse_str("{ let todo: Todo = Todo::new(); }")?;
+        let details = extract_locals(&block, FileItems::from_items(&items.items))?;
+        let typed = |expr: &str| {
+            let ty = expr_type(
+                &syn::parse_str(expr).unwrap(),
+                &details.states,
+                &details.items,
+            );
+            quote!(#ty).to_string()
+        };
+        assert_eq!(typed("compute()"), "u32");
+        assert_eq!(typed("parse()"), "");
+        assert_eq!(typed("Todo::new()"), "Todo");
+        assert_eq!(typed("todo.label()"), "& str");
+        assert_eq!(typed("Meters(1.0)"), "Meters");
+        assert_eq!(typed("Status::Loading"), "Status");
+        assert_eq!(typed("Status::Loaded(1)"), "Status");
+        assert_eq!(typed("Some(compute())"), "Option < u32 >");
+        assert_eq!(typed("vec![compute(), 2]"), "Vec < u32 >");
+        assert_eq!(typed("format!(\"{}\", 1)"), "String");
+        assert_eq!(typed("todo.save().await"), "Result < Todo , String >");
+        let output = future_output_type(
+            &syn::parse_str("todo.save()")?,
+            &details.states,
+            &details.items,
+        );
+        assert_eq!(quote!(#output).to_string(), "Result < Todo , String >");
+        Ok(())
+    }
+
+    #[test]
+    fn local_imports() -> syn::Result<()> {
+        let items = syn::parse_file(
+            "mod models { pub enum Status { Loading } }
+            use crate::models::Status;
+            use models::*;
+            use Status::*;
+            use serde::Serialize;",
+        )?;
+        let declarations = FileItems::from_items(&items.items).declarations;
+        assert!(declarations.contains("use crate :: models :: Status ;"));
+        assert!(declarations.contains("use models :: * ;"));
+        assert!(declarations.contains("use Status :: * ;"));
+        assert!(!declarations.contains("serde"));
+        Ok(())
+    }
+
+    #[test]
+    fn method_receivers() -> syn::Result<()> {
+        let block = parse_state_block.parse_str(
+            "{
+                let items: Vec<u32> = Vec::new();
+                let point: Point = Point::default();
+                let other: Other = Other::default();
+            }",
+        )?;
+        let details = extract_locals(&block, FileItems::default())?;
+        let items = syn::parse_file(
+            "struct Point { list: Vec<u8> }
+            impl Point { fn reset(&mut self) {} fn norm(&self) -> f64 { 0.0 } }",
+        )?;
+        let receivers = Receivers::new(&details.states, &FileItems::from_items(&items.items));
+        let is_mutable = |call: &str| match syn::parse_str(call).unwrap() {
+            syn::Expr::MethodCall(call) => receivers.is_mutable(&call.receiver, &call.method),
+            _ => unreachable!(),
+        };
+        assert!(!is_mutable("items.len()"));
+        assert!(is_mutable("items.push(1)"));
+        assert!(!is_mutable("items[0].pow(2)"));
+        assert!(!is_mutable("point.list.is_empty()"));
+        assert!(is_mutable("point.list.clear()"));
+        assert!(is_mutable("point.reset()"));
+        assert!(!is_mutable("point.norm()"));
+        assert!(is_mutable("other.frob()"));
+        assert!(!is_mutable("other.clone()"));
+        // The receiver's type is unknown, `clone` could be any method
+        assert!(is_mutable("other.frob().clone()"));
+        Ok(())
+    }
+}
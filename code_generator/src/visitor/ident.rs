@@ -6,10 +6,13 @@ use syn::{
     parse_str,
     visit::{self, Visit},
     visit_mut::{self, VisitMut},
-    Error, Expr, ExprAssign, ExprAssignOp, ExprBinary, ExprMacro, ExprMethodCall, ExprPath, Ident,
-    Local, Result,
+    Arm, Block, Error, Expr, ExprAssign, ExprAssignOp, ExprClosure, ExprField, ExprForLoop, ExprIf,
+    ExprIndex, ExprLet, ExprMacro, ExprMethodCall, ExprParen, ExprPath, ExprReference, ExprUnary,
+    ExprWhile, Ident, Item, Local, Pat, PatIdent, PatTuple, Result, UnOp,
 };
 
+use crate::types::Receivers;
+
 pub struct IdentExtractor {
     pub idents: Vec<Ident>,
 }
@@ -23,10 +26,6 @@ impl IdentExtractor {
         self.idents.iter().map(|ident| ident.to_string()).collect()
     }
 
-    fn intersect_names(&self, names: &HashSet<String>) -> HashSet<String> {
-        self.names().intersection(names).cloned().collect()
-    }
-
     #[allow(dead_code)]
     fn ident_from_intersect_names(&self, names: &HashSet<String>) -> HashSet<String> {
         self.names().intersection(names).cloned().collect()
@@ -40,6 +39,25 @@ impl<'ast> Visit<'ast> for IdentExtractor {
     }
 }
 
+/// Idents bound by a pattern (ex: `Some((a, b))` -> a, b).
+/// Unit variants and constants (ex: `None`) are collected too.
+pub struct BindingExtractor {
+    pub idents: Vec<Ident>,
+}
+
+impl BindingExtractor {
+    pub fn new() -> Self {
+        Self { idents: Vec::new() }
+    }
+}
+
+impl<'ast> Visit<'ast> for BindingExtractor {
+    fn visit_pat_ident(&mut self, node: &'ast PatIdent) {
+        self.idents.push(node.ident.clone());
+        visit::visit_pat_ident(self, node);
+    }
+}
+
 pub struct IdentModifier {
     pub state_names: HashSet<String>,
     pub names: HashSet<String>,
@@ -48,7 +66,14 @@ pub struct IdentModifier {
     pub locals: HashSet<String>,
     pub errors: Vec<Error>,
     pub count_expr_path: usize,
+    /// `&mut State` held by a handler for its whole body, the states are its fields
+    /// instead of a borrow for each access, which could conflict in one statement.
+    pub guard: Option<String>,
+    /// Tells if a method called on a state writes it, by default they all may.
+    pub receivers: Receivers,
     state_ident: String,
+    /// Bindings of the visited blocks, closures, arms and loops, they shadow the states.
+    scopes: Vec<HashSet<String>>,
 }
 
 impl IdentModifier {
@@ -61,7 +86,10 @@ impl IdentModifier {
             locals: HashSet::new(),
             errors: Vec::new(),
             count_expr_path: 0,
+            guard: None,
+            receivers: Receivers::default(),
             state_ident,
+            scopes: Vec::new(),
         }
     }
 
@@ -82,62 +110,76 @@ impl IdentModifier {
         Ok(())
     }
 
-    fn replace_expr(&mut self, node: &mut Box<Expr>, prefix: &str, suffix: &str) {
-        let mut visitor = IdentExtractor::new();
-        visitor.visit_expr(&node);
-        let names = visitor.intersect_names(&self.state_names);
-        if names.len() == 1 && visitor.idents.len() == 1 {
-            let to_parse = format!("{}{}{}", prefix, quote!(#node).to_string(), suffix);
-            self.try_parse_node(node, to_parse, names);
-            // match parse_str::<Expr>(&format!(
-            //     "{}{}{}",
-            //     prefix,
-            //     quote!(#node).to_string(),
-            //     suffix
-            // )) {
-            //     Ok(new_node) => {
-            //         self.modified.extend(names);
-            //         **node = new_node;
-            //     }
-            //     Err(err) => {
-            //         err.span().error(err.to_string());
-            //         self.errors.push(err);
-            //     }
-            // }
-        } else if names.len() == 1 {
-            // TODO once implemented do not forget to add it to modified names
-            // self.modified.extend(names);
-            let span = visitor
+    fn is_state(&self, path: &ExprPath) -> bool {
+        //! A state is read through its name, unless a local binding shadows it.
+        match (&path.qself, path.path.get_ident()) {
+            (None, Some(ident)) => {
+                let name = ident.to_string();
+                self.state_names.contains(&name)
+                    && !self.scopes.iter().any(|scope| scope.contains(&name))
+            }
+            _ => false,
+        }
+    }
+
+    fn replace_state(&mut self, node: &mut Expr, mutable: bool) {
+        //! Replace a state's name by its field, borrowed mutably when it's written.
+        let name = quote!(#node).to_string();
+        let to_parse = match &self.guard {
+            Some(guard) => format!("{guard}.{name}"),
+            None if mutable => format!("{}.borrow_mut().{name}", self.state_ident),
+            None => format!("{}.borrow().{name}", self.state_ident),
+        };
+        self.names.insert(name.clone());
+        if mutable {
+            self.names_refmut.insert(name);
+        } else {
+            self.names_ref.insert(name);
+        }
+        self.try_parse_node(node, to_parse);
+    }
+
+    fn visit_place_mut(&mut self, node: &mut Expr) {
+        //! A written place (ex: `items[index].count`), its root state is borrowed mutably
+        //! and the indexes are read.
+        match node {
+            Expr::Path(path) if self.is_state(path) => self.replace_state(node, true),
+            Expr::Field(ExprField { base, .. }) => self.visit_place_mut(base),
+            Expr::Index(ExprIndex { expr, index, .. }) => {
+                self.visit_place_mut(expr);
+                self.visit_expr_mut(index);
+            }
+            Expr::Paren(ExprParen { expr, .. }) => self.visit_place_mut(expr),
+            Expr::Unary(ExprUnary {
+                op: UnOp::Deref(_),
+                expr,
+                ..
+            }) => self.visit_place_mut(expr),
+            _ => self.visit_expr_mut(node),
+        }
+    }
+
+    fn visit_scoped<T>(&mut self, pat: &Pat, node: &mut T, visit: fn(&mut Self, &mut T)) {
+        //! Visit a node where the pattern's bindings shadow the states.
+        let mut extractor = BindingExtractor::new();
+        extractor.visit_pat(pat);
+        self.scopes.push(
+            extractor
                 .idents
                 .iter()
-                .filter(|ident| ident.to_string() == *names.iter().next().unwrap())
-                .next()
-                .unwrap()
-                .span();
-            let msg = format!(
-                "The left side of ExprAssignOp with multiple ident is not handle.\
-                    If there's an use case : see `{}:{}` to implement it.",
-                file!(),
-                line!()
-            );
-            span.error(msg.clone());
-            self.errors.push(Error::new(span, msg));
-        }
+                .map(|ident| ident.to_string())
+                .collect(),
+        );
+        visit(self, node);
+        self.scopes.pop();
     }
 
-    fn try_parse_node<P>(&mut self, node: &mut P, to_parse: String, name: HashSet<String>)
+    fn try_parse_node<P>(&mut self, node: &mut P, to_parse: String)
     where
         P: syn::parse::Parse,
     {
         match parse_str(to_parse.as_str()) {
-            Ok(new_node) => {
-                if to_parse.contains("borrow_mut") {
-                    self.names_refmut.extend(name);
-                } else if to_parse.contains("borrow") {
-                    self.names_ref.extend(name);
-                }
-                *node = new_node;
-            }
+            Ok(new_node) => *node = new_node,
             Err(err) => {
                 err.span().error(err.to_string());
                 self.errors.push(err);
@@ -147,88 +189,128 @@ impl IdentModifier {
 }
 
 impl VisitMut for IdentModifier {
+    fn visit_block_mut(&mut self, node: &mut Block) {
+        self.scopes.push(HashSet::new());
+        visit_mut::visit_block_mut(self, node);
+        self.scopes.pop();
+    }
+
     fn visit_local_mut(&mut self, node: &mut Local) {
-        match node {
-            Local {
-                pat: syn::Pat::Ident(syn::PatIdent { ident, .. }),
-                ..
-            } => self.locals.insert(ident.to_string()),
-            _ => false,
-        };
-        visit_mut::visit_local_mut(self, node);
+        // The value is computed before its bindings shadow the states
+        if let Some((_, init)) = &mut node.init {
+            self.visit_expr_mut(init);
+        }
+        let mut extractor = BindingExtractor::new();
+        extractor.visit_pat(&node.pat);
+        for ident in extractor.idents {
+            self.locals.insert(ident.to_string());
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.insert(ident.to_string());
+            }
+        }
+    }
+
+    fn visit_item_mut(&mut self, _node: &mut Item) {
+        // Items can't capture the states
     }
 
-    fn visit_expr_binary_mut(&mut self, node: &mut ExprBinary) {
-        let borrow = format!("{}.borrow().", self.state_ident);
-        match *node.right {
-            Expr::Binary(_) => (),
-            _ => self.replace_expr(&mut node.left, borrow.as_str(), ""),
+    fn visit_expr_closure_mut(&mut self, node: &mut ExprClosure) {
+        let params = node.inputs.iter().cloned().collect();
+        let pat = Pat::Tuple(PatTuple {
+            attrs: Vec::new(),
+            paren_token: Default::default(),
+            elems: params,
+        });
+        self.visit_scoped(&pat, &mut *node.body, Self::visit_expr_mut);
+    }
+
+    fn visit_arm_mut(&mut self, node: &mut Arm) {
+        let pat = node.pat.clone();
+        self.visit_scoped(&pat, node, |visitor, arm| {
+            if let Some((_, guard)) = &mut arm.guard {
+                visitor.visit_expr_mut(guard);
+            }
+            visitor.visit_expr_mut(&mut arm.body);
+        });
+    }
+
+    fn visit_expr_for_loop_mut(&mut self, node: &mut ExprForLoop) {
+        self.visit_expr_mut(&mut node.expr);
+        let pat = node.pat.clone();
+        self.visit_scoped(&pat, &mut node.body, Self::visit_block_mut);
+    }
+
+    fn visit_expr_if_mut(&mut self, node: &mut ExprIf) {
+        match &mut *node.cond {
+            // `if let` bindings are only seen by the first branch
+            Expr::Let(ExprLet { pat, expr, .. }) => {
+                self.visit_expr_mut(expr);
+                let pat = pat.clone();
+                self.visit_scoped(&pat, &mut node.then_branch, Self::visit_block_mut);
+            }
+            cond => {
+                self.visit_expr_mut(cond);
+                self.visit_block_mut(&mut node.then_branch);
+            }
+        }
+        if let Some((_, else_branch)) = &mut node.else_branch {
+            self.visit_expr_mut(else_branch);
         }
-        self.replace_expr(&mut node.right, borrow.as_str(), "");
-        visit_mut::visit_expr_binary_mut(self, node);
     }
 
-    fn visit_expr_assign_op_mut(&mut self, node: &mut ExprAssignOp) {
-        let borrow = format!("{}.borrow().", self.state_ident);
-        let borrow_mut = format!("{}.borrow_mut().", self.state_ident);
-        self.replace_expr(&mut node.left, borrow_mut.as_str(), "");
-        match *node.right {
-            Expr::Binary(_) => (),
-            _ => self.replace_expr(&mut node.right, borrow.as_str(), ""),
+    fn visit_expr_while_mut(&mut self, node: &mut ExprWhile) {
+        match &mut *node.cond {
+            Expr::Let(ExprLet { pat, expr, .. }) => {
+                self.visit_expr_mut(expr);
+                let pat = pat.clone();
+                self.visit_scoped(&pat, &mut node.body, Self::visit_block_mut);
+            }
+            cond => {
+                self.visit_expr_mut(cond);
+                self.visit_block_mut(&mut node.body);
+            }
         }
-        visit_mut::visit_expr_assign_op_mut(self, node);
+    }
+
+    fn visit_expr_assign_op_mut(&mut self, node: &mut ExprAssignOp) {
+        self.visit_place_mut(&mut node.left);
+        self.visit_expr_mut(&mut node.right);
     }
 
     fn visit_expr_assign_mut(&mut self, node: &mut ExprAssign) {
-        let borrow = format!("{}.borrow().", self.state_ident);
-        let borrow_mut = format!("{}.borrow_mut().", self.state_ident);
-        self.replace_expr(&mut node.left, borrow_mut.as_str(), "");
-        match *node.right {
-            Expr::Binary(_) => (),
-            _ => self.replace_expr(&mut node.right, borrow.as_str(), ""),
+        self.visit_place_mut(&mut node.left);
+        self.visit_expr_mut(&mut node.right);
+    }
+
+    fn visit_expr_reference_mut(&mut self, node: &mut ExprReference) {
+        match node.mutability {
+            Some(_) => self.visit_place_mut(&mut node.expr),
+            None => self.visit_expr_mut(&mut node.expr),
         }
-        visit_mut::visit_expr_assign_mut(self, node);
     }
 
     fn visit_expr_method_call_mut(&mut self, node: &mut ExprMethodCall) {
-        let borrow_mut = format!("{}.borrow_mut().", self.state_ident);
-        match *node.receiver {
-            Expr::Path(ExprPath { ref path, .. }) => {
-                let name = path.segments[0].ident.to_string();
-                if self.state_names.contains(&name) {
-                    let to_parse = format!("{borrow_mut}{}", quote!(#node));
-                    // self.try_parse_node::<ExprMethodCall, _>(node, to_parse, name);
-                    self.try_parse_node(node, to_parse, HashSet::from([name]));
-                    // match parse_str(to_parse.as_str()) {
-                    //     Ok(new_node) => {
-                    //         self.modified.insert(name);
-                    //         *node = new_node;
-                    //     }
-                    //     Err(err) => {
-                    //         err.span().error(err.to_string());
-                    //         self.errors.push(err);
-                    //     }
-                    // }
-                }
-            }
-            _ => (),
+        // Only the methods which may take `&mut self` write their receiver
+        let shared = place_root(&node.receiver).is_some_and(|root| self.is_state(root))
+            && !self.receivers.is_mutable(&node.receiver, &node.method);
+        if shared {
+            self.visit_expr_mut(&mut node.receiver);
+        } else {
+            self.visit_place_mut(&mut node.receiver);
+        }
+        for arg in node.args.iter_mut() {
+            self.visit_expr_mut(arg);
         }
-        visit_mut::visit_expr_method_call_mut(self, node);
     }
 
     fn visit_expr_mut(&mut self, node: &mut Expr) {
-        let borrow = format!("{}.borrow().", self.state_ident);
-        match *node {
-            Expr::Path(ExprPath { ref path, .. }) => {
-                let name = path.segments[0].ident.to_string();
-                if self.state_names.contains(&name) {
-                    let to_parse = format!("{borrow}{}", quote!(#node));
-                    self.try_parse_node(node, to_parse, HashSet::from([name]));
-                }
+        match node {
+            Expr::Path(path) if self.is_state(path) => {
+                self.count_expr_path += 1;
+                self.replace_state(node, false);
             }
-            _ => (),
+            _ => visit_mut::visit_expr_mut(self, node),
         }
-        visit_mut::visit_expr_mut(self, node);
     }
 
     fn visit_expr_path_mut(&mut self, node: &mut ExprPath) {
@@ -256,6 +338,92 @@ impl VisitMut for IdentModifier {
     }
 }
 
+fn place_root(place: &Expr) -> Option<&ExprPath> {
+    //! Variable of a place (ex: `items` for `items[index].count`).
+    match place {
+        Expr::Path(path) => Some(path),
+        Expr::Field(ExprField { base: expr, .. })
+        | Expr::Index(ExprIndex { expr, .. })
+        | Expr::Paren(ExprParen { expr, .. })
+        | Expr::Unary(ExprUnary {
+            op: UnOp::Deref(_),
+            expr,
+            ..
+        }) => place_root(expr),
+        _ => None,
+    }
+}
+
+/// Prefix idents coming from a sub dom's `Props` (ex: `item` -> `self.props.item`).
+pub struct PropsModifier {
+    pub props: HashSet<String>,
+    pub used: HashSet<String>,
+    prefix: String,
+}
+
+impl PropsModifier {
+    pub fn new(props: HashSet<String>, prefix: String) -> Self {
+        Self {
+            props,
+            used: HashSet::new(),
+            prefix,
+        }
+    }
+}
+
+impl VisitMut for PropsModifier {
+    fn visit_expr_mut(&mut self, node: &mut Expr) {
+        if let Expr::Path(ExprPath { ref path, .. }) = *node {
+            if let Some(ident) = path.get_ident() {
+                let name = ident.to_string();
+                if self.props.contains(&name) {
+                    *node = parse_str(&format!("{}.{}", self.prefix, name)).unwrap();
+                    self.used.insert(name);
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_expr_mut(self, node);
+    }
+}
+
+/// Replace `store!(count)` (`$count` in the markup) by the value of the state's store.
+pub struct StoreModifier {
+    pub used: HashSet<String>,
+    /// Borrowed `State` the stores are read from, like `IdentModifier.guard`.
+    pub guard: Option<String>,
+    state_ident: String,
+}
+
+impl StoreModifier {
+    pub fn new(state_ident: String) -> Self {
+        Self {
+            used: HashSet::new(),
+            guard: None,
+            state_ident,
+        }
+    }
+}
+
+impl VisitMut for StoreModifier {
+    fn visit_expr_mut(&mut self, node: &mut Expr) {
+        if let Expr::Macro(ExprMacro { ref mac, .. }) = *node {
+            if mac.path.is_ident("store") {
+                if let Ok(ident) = mac.parse_body::<Ident>() {
+                    let state = match &self.guard {
+                        Some(guard) => guard.clone(),
+                        None => format!("{}.borrow()", self.state_ident),
+                    };
+                    *node = parse_str(&format!("{state}.{ident}.get()")).unwrap();
+                    self.used.insert(ident.to_string());
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_expr_mut(self, node);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{IdentModifier, VisitMut};
@@ -268,6 +436,74 @@ mod tests {
         assert_impl_display::<&str>();
     }
 
+    #[test]
+    fn scoped_places() -> syn::Result<()> {
+        let mut block: syn::Block = syn::parse_str(
+            "{
+                items[index] += 1;
+                point.x = step;
+                let total = total + 1;
+                log(total);
+                sizes.iter().map(|step| step * 2);
+                match point { Point { x: step, .. } => step, }
+            }",
+        )?;
+        let mut ident_visitor = IdentModifier::new(
+            ["items", "point", "step", "total", "sizes"]
+                .map(String::from)
+                .into(),
+            "s".to_string(),
+        );
+        // Generated code borrows the state once when it's written, see `Dom::rewrite_expr`
+        ident_visitor.guard = Some("state".to_string());
+        ident_visitor.visit_block_mut(&mut block);
+        let stmts = block
+            .stmts
+            .iter()
+            .map(|stmt| quote!(#stmt).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            [
+                "state . items [index] += 1 ;",
+                "state . point . x = state . step ;",
+                "let total = state . total + 1 ;",
+                "log (total) ;",
+                "state . sizes . iter () . map (| step | step * 2) ;",
+                "match state . point { Point { x : step , .. } => step , }",
+            ]
+        );
+        // No state borrow overlaps a mutable one
+        assert!(stmts.iter().all(|stmt| !stmt.contains("borrow")));
+        assert_eq!(
+            ident_visitor.names_ref,
+            ["step", "total", "point"].map(String::from).into()
+        );
+        assert_eq!(
+            ident_visitor.names_refmut,
+            ["items", "point", "sizes"].map(String::from).into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn guarded_handler() -> syn::Result<()> {
+        let mut closure: syn::ExprClosure = syn::parse_str("|_| total += price")?;
+        let mut ident_visitor =
+            IdentModifier::new(["total", "price"].map(String::from).into(), "s".to_string());
+        ident_visitor.guard = Some("state".to_string());
+        ident_visitor.visit_expr_closure_mut(&mut closure);
+        assert_eq!(
+            quote!(#closure).to_string(),
+            "| _ | state . total += state . price"
+        );
+        assert_eq!(
+            ident_visitor.names_refmut,
+            HashSet::from(["total".to_string()])
+        );
+        Ok(())
+    }
+
     #[test]
     fn plop() -> syn::Result<()> {
         // let block = r#"{plop}"#;
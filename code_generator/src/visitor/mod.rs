@@ -2,4 +2,4 @@ pub use syn::visit::Visit;
 
 pub mod ident;
 
-pub use ident::{IdentExtractor, IdentModifier};
+pub use ident::{BindingExtractor, IdentExtractor, IdentModifier, PropsModifier, StoreModifier};
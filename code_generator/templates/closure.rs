@@ -4,8 +4,14 @@ let //ITEM <%= name %>
     let s = state.clone();
     move |event: web_sys::Event| {
         let s = s.clone();
-        //FOR <% for stmt in &statements { %>
-        //ITEM <%= stmt %> <% } %>
+        {
+            // One borrow for the whole body, its reads and writes of the states can't conflict
+            let mut guard = s.borrow_mut();
+            #[allow(unused_variables)]
+            let state = &mut *guard;
+            //FOR <% for stmt in &statements { %>
+            //ITEM <%= stmt %> <% } %>
+        }
         if !r.borrow().dirty_ids.contains(&id) {
             r.borrow_mut().dirty_ids.insert(id);
         }
@@ -2,6 +2,7 @@ use std::{
     any::Any,
     cell::RefCell,
     collections::{HashMap, HashSet},
+    hash::Hash,
     rc::Rc,
 };
 
@@ -100,17 +101,323 @@ macro_rules! remove_listener {
 trait DOMExt {
     fn mount(&mut self, parent: &web_sys::Element) -> Result<(), JsValue>;
     fn update(&mut self) -> Result<(), JsValue>;
+    /// Move the already mounted nodes before `target`.
+    fn move_before(&self, target: &web_sys::Element) -> Result<(), JsValue>;
+    /// Listen to the events of the top level nodes (used by a parent component).
+    fn listen(
+        &self,
+        event_type: &str,
+        callback: &Closure<dyn FnMut(web_sys::Event)>,
+    ) -> Result<(), JsValue>;
     fn as_any(&self) -> &dyn Any;
 }
 
+/// Sub dom of a keyed `{for}`, its nodes follow the `start` marker.
+struct Keyed<D: DOMExt> {
+    /// Key of the loop (`K` of `reconcile_keyed`), compared to the new ones.
+    key: Box<dyn Any>,
+    start: Text,
+    dom: D,
+}
+
+impl<D: DOMExt> Keyed<D> {
+    fn move_before(&self, target: &web_sys::Element) -> Result<(), JsValue> {
+        let parent = target.parent_node().unwrap_throw();
+        parent.insert_before(&self.start, Some(target))?;
+        self.dom.move_before(target)
+    }
+}
+
+impl<D: DOMExt> Drop for Keyed<D> {
+    fn drop(&mut self) {
+        self.start.remove();
+    }
+}
+
+//ITEM <%= KEYED_RUNTIME %>
+
+/// Reuse sub doms by key, create the missing ones and drop the removed ones.
+/// Only sub doms outside a longest increasing subsequence of old positions are moved.
+fn reconcile_keyed<D: DOMExt, K: Hash + Eq + 'static, P>(
+    rows: &mut Vec<Keyed<D>>,
+    items: Vec<(K, P)>,
+    anchor: &web_sys::Element,
+    mut create: impl FnMut(u32, P) -> Result<D, JsValue>,
+    mut update: impl FnMut(&mut D, P) -> Result<(), JsValue>,
+) -> Result<(), JsValue> {
+    let document = document!();
+    let sources = {
+        let old_keys: Vec<Option<&K>> = rows.iter().map(|row| row.key.downcast_ref()).collect();
+        let new_keys: Vec<&K> = items.iter().map(|(key, _)| key).collect();
+        keyed_sources(&old_keys, &new_keys)
+    };
+    let mut old_rows: Vec<Option<Keyed<D>>> = rows.drain(..).map(Some).collect();
+
+    let mut new_rows = Vec::with_capacity(items.len());
+    for (i, ((key, props), source)) in items.into_iter().zip(&sources).enumerate() {
+        match source.and_then(|old| old_rows[old].take()) {
+            Some(mut row) => {
+                update(&mut row.dom, props)?;
+                new_rows.push(row);
+            }
+            None => new_rows.push(Keyed {
+                key: Box::new(key),
+                start: document.create_text_node(""),
+                dom: create(i as u32, props)?,
+            }),
+        }
+    }
+    // Rows which are not reused remove their nodes
+    drop(old_rows);
+
+    let stable = stable_rows(&sources);
+    let mut next: web_sys::Element = anchor.clone();
+    for (i, row) in new_rows.iter_mut().enumerate().rev() {
+        if sources[i].is_none() {
+            let parent = next.parent_node().unwrap_throw();
+            parent.insert_before(&row.start, Some(&next))?;
+            row.dom.mount(&next)?;
+        } else if !stable.contains(&i) {
+            row.move_before(&next)?;
+        }
+        next = row.start.clone().unchecked_into();
+    }
+    *rows = new_rows;
+    Ok(())
+}
+
+/// Value of an attribute, `None` removes it (ex: `disabled={false}`).
+trait AttributeValue {
+    fn attribute_value(&self) -> Option<String>;
+}
+
+impl AttributeValue for bool {
+    fn attribute_value(&self) -> Option<String> {
+        self.then(String::new)
+    }
+}
+
+impl<T: AttributeValue> AttributeValue for Option<T> {
+    fn attribute_value(&self) -> Option<String> {
+        self.as_ref().and_then(AttributeValue::attribute_value)
+    }
+}
+
+macro_rules! impl_attribute_value_to_string {
+    ($($type:ty),*) => {
+        $(
+            impl AttributeValue for $type {
+                fn attribute_value(&self) -> Option<String> {
+                    Some(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_attribute_value_to_string!(
+    String, &str, char, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64
+);
+
+fn set_attribute_value<T: AttributeValue>(
+    element: &web_sys::Element,
+    name: &str,
+    value: &T,
+) -> Result<(), JsValue> {
+    match value.attribute_value() {
+        Some(value) => element.set_attribute(name, &value),
+        None => element.remove_attribute(name),
+    }
+}
+
 //ITEM <%= dom._state.borrow().clone().render_once().unwrap() %>
 
 //ITEM <%= dom.render_once().unwrap() %>
 
+pub mod stores {
+    //! Stores share a value between components and plain Rust code, their subscribers
+    //! are called each time the value changes. In the markup, `$store` reads the value
+    //! and subscribes the component : a change marks it dirty.
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    pub trait Store<T> {
+        fn get(&self) -> T;
+        /// Return the id used to unsubscribe.
+        fn subscribe(&self, subscriber: impl Fn(&T) + 'static) -> u32;
+        fn unsubscribe(&self, id: u32);
+    }
+
+    struct Inner<T> {
+        value: T,
+        subscribers: HashMap<u32, Rc<dyn Fn(&T)>>,
+        next_id: u32,
+    }
+
+    /// Store which can be set by anyone having it.
+    pub struct Writable<T> {
+        inner: Rc<RefCell<Inner<T>>>,
+    }
+
+    impl<T> Clone for Writable<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+            }
+        }
+    }
+
+    impl<T: Clone + 'static> Writable<T> {
+        pub fn new(value: T) -> Self {
+            Self {
+                inner: Rc::new(RefCell::new(Inner {
+                    value,
+                    subscribers: HashMap::new(),
+                    next_id: 0,
+                })),
+            }
+        }
+
+        pub fn set(&self, value: T) {
+            self.inner.borrow_mut().value = value;
+            self.notify();
+        }
+
+        pub fn update(&self, update: impl FnOnce(&mut T)) {
+            update(&mut self.inner.borrow_mut().value);
+            self.notify();
+        }
+
+        pub fn readable(&self) -> Readable<T> {
+            Readable {
+                store: self.clone(),
+            }
+        }
+
+        fn notify(&self) {
+            // Nothing is borrowed while subscribers run : they can use the store
+            let (value, subscribers): (T, Vec<_>) = {
+                let inner = self.inner.borrow();
+                (
+                    inner.value.clone(),
+                    inner.subscribers.values().cloned().collect(),
+                )
+            };
+            for subscriber in subscribers {
+                subscriber(&value);
+            }
+        }
+    }
+
+    impl<T: Clone + 'static> Store<T> for Writable<T> {
+        fn get(&self) -> T {
+            self.inner.borrow().value.clone()
+        }
+
+        fn subscribe(&self, subscriber: impl Fn(&T) + 'static) -> u32 {
+            let mut inner = self.inner.borrow_mut();
+            let id = inner.next_id;
+            inner.next_id += 1;
+            inner.subscribers.insert(id, Rc::new(subscriber));
+            id
+        }
+
+        fn unsubscribe(&self, id: u32) {
+            self.inner.borrow_mut().subscribers.remove(&id);
+        }
+    }
+
+    /// Read only view of a `Writable`.
+    pub struct Readable<T> {
+        store: Writable<T>,
+    }
+
+    impl<T> Clone for Readable<T> {
+        fn clone(&self) -> Self {
+            Self {
+                store: self.store.clone(),
+            }
+        }
+    }
+
+    impl<T: Clone + 'static> Store<T> for Readable<T> {
+        fn get(&self) -> T {
+            self.store.get()
+        }
+
+        fn subscribe(&self, subscriber: impl Fn(&T) + 'static) -> u32 {
+            self.store.subscribe(subscriber)
+        }
+
+        fn unsubscribe(&self, id: u32) {
+            self.store.unsubscribe(id)
+        }
+    }
+
+    /// Subscription to a source store, removed when the last `Derived` using it is dropped.
+    struct Subscription(Box<dyn Fn()>);
+
+    impl Drop for Subscription {
+        fn drop(&mut self) {
+            (self.0)()
+        }
+    }
+
+    /// Value computed from another store, recomputed each time it changes.
+    pub struct Derived<T> {
+        store: Writable<T>,
+        subscription: Rc<Subscription>,
+    }
+
+    impl<T> Clone for Derived<T> {
+        fn clone(&self) -> Self {
+            Self {
+                store: self.store.clone(),
+                subscription: self.subscription.clone(),
+            }
+        }
+    }
+
+    impl<T: Clone + 'static> Derived<T> {
+        pub fn new<S>(
+            source: &(impl Store<S> + Clone + 'static),
+            compute: impl Fn(&S) -> T + 'static,
+        ) -> Self {
+            let store = Writable::new(compute(&source.get()));
+            let target = store.clone();
+            let id = source.subscribe(move |value| target.set(compute(value)));
+            let source = source.clone();
+            Self {
+                store,
+                subscription: Rc::new(Subscription(Box::new(move || source.unsubscribe(id)))),
+            }
+        }
+    }
+
+    impl<T: Clone + 'static> Store<T> for Derived<T> {
+        fn get(&self) -> T {
+            self.store.get()
+        }
+
+        fn subscribe(&self, subscriber: impl Fn(&T) + 'static) -> u32 {
+            self.store.subscribe(subscriber)
+        }
+
+        fn unsubscribe(&self, id: u32) {
+            self.store.unsubscribe(id)
+        }
+    }
+}
+
+#[allow(unused_imports)]
+use stores::Store as _;
+
 pub struct Runtime {
     key: u32,
-    components: HashMap<u32, Rc<RefCell<Box<dyn DOMExt>>>>,
+    components: HashMap<u32, Rc<RefCell<dyn DOMExt>>>,
     dirty_ids: HashSet<u32>,
+    /// Parent of each child component.
+    parents: HashMap<u32, u32>,
+    contexts: HashMap<u32, HashMap<String, Rc<dyn Any>>>,
 }
 
 impl Runtime {
@@ -119,6 +426,8 @@ impl Runtime {
             key: 0,
             dirty_ids: HashSet::new(),
             components: HashMap::new(),
+            parents: HashMap::new(),
+            contexts: HashMap::new(),
         }
     }
 
@@ -127,6 +436,77 @@ impl Runtime {
         self.key += 1;
         key
     }
+
+    fn remove_contexts(&mut self, id: u32) {
+        self.parents.remove(&id);
+        self.contexts.remove(&id);
+    }
+}
+
+thread_local! {
+    /// Component whose state's block is running.
+    static CURRENT_COMPONENT: RefCell<Option<(Rc<RefCell<Runtime>>, u32)>> = RefCell::new(None);
+}
+
+struct CurrentComponent {
+    previous: Option<(Rc<RefCell<Runtime>>, u32)>,
+}
+
+impl CurrentComponent {
+    fn enter(runtime: Rc<RefCell<Runtime>>, id: u32) -> Self {
+        let previous = CURRENT_COMPONENT.with(|current| current.replace(Some((runtime, id))));
+        Self { previous }
+    }
+}
+
+impl Drop for CurrentComponent {
+    fn drop(&mut self) {
+        CURRENT_COMPONENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+fn current_component(function: &str) -> (Rc<RefCell<Runtime>>, u32) {
+    CURRENT_COMPONENT
+        .with(|current| current.borrow().clone())
+        .unwrap_or_else(|| panic!("`{function}` must be called in a state's block."))
+}
+
+/// Give a value to the child components (and their children), see `get_context`.
+pub fn set_context<T: 'static>(key: &str, value: T) {
+    let (runtime, id) = current_component("set_context");
+    runtime
+        .borrow_mut()
+        .contexts
+        .entry(id)
+        .or_default()
+        .insert(key.to_string(), Rc::new(value));
+}
+
+/// Value set by the closest ancestor component with this key.
+/// Panic if no ancestor set it or if its type isn't `T`.
+pub fn get_context<T: Clone + 'static>(key: &str) -> T {
+    let (runtime, id) = current_component("get_context");
+    let runtime = runtime.borrow();
+    let mut ancestor = runtime.parents.get(&id);
+    while let Some(ancestor_id) = ancestor {
+        if let Some(value) = runtime
+            .contexts
+            .get(ancestor_id)
+            .and_then(|contexts| contexts.get(key))
+        {
+            return value
+                .downcast_ref::<T>()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Context `{key}` isn't a `{}`.",
+                        std::any::type_name::<T>()
+                    )
+                })
+                .clone();
+        }
+        ancestor = runtime.parents.get(ancestor_id);
+    }
+    panic!("Context `{key}` isn't set by an ancestor component.");
 }
 
 #[wasm_bindgen(start)]
@@ -137,36 +517,30 @@ pub async fn run() -> Result<(), JsValue> {
     // // let _component = DOM::new(runtime, body.into());
     let mut new_component = DOM::new(runtime.clone()).unwrap_throw();
     new_component.mount(body!().unchecked_ref())?;
-    runtime.borrow_mut().components.insert(
-        new_component.id,
-        Rc::new(RefCell::new(Box::new(new_component))),
-    );
+    runtime
+        .borrow_mut()
+        .components
+        .insert(new_component.id, Rc::new(RefCell::new(new_component)));
     let mut i = 0;
     loop {
         // sleep(Duration::from_secs(1));
         next_tick!();
 
-        runtime.borrow().dirty_ids.iter().for_each(|id| {
-            console::log_2(
-                &"dirty : ".into(),
-                &format!("dirtys ids {:?}", runtime.borrow().dirty_ids).into(),
-            );
-            console::log_2(&"dirty : ".into(), &id.clone().into());
+        // Runtime isn't borrowed during updates : they can create or drop child components
+        let dirty_ids: Vec<u32> = runtime.borrow_mut().dirty_ids.drain().collect();
+        for id in dirty_ids {
+            console::log_2(&"dirty : ".into(), &id.into());
             // console::log_2(
             //     &"Component details : ".into(),
             //     &component.state.counter.into(),
             // );
-            runtime
-                .borrow()
-                .components
-                .get(id)
-                .unwrap_throw()
-                .borrow_mut()
-                .update()
-                .unwrap_throw();
+            // A dropped child component is no longer registered
+            let component = runtime.borrow().components.get(&id).cloned();
+            if let Some(component) = component {
+                component.borrow_mut().update().unwrap_throw();
+            }
             i += 1;
-        });
-        runtime.borrow_mut().dirty_ids.clear();
+        }
         // FIXME: TODO: Considere removing this break (block infinite loop during dev)
         if i > 100 {
             console::log_1(&"DEV Break main loop (block infinite loop during dev)".into());
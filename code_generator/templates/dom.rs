@@ -7,11 +7,52 @@
 pub struct Props {
     //FOR <% for (ident, type_) in &props { %>
     //ITEM pub <%= ident %>: <%= type_ %>, <% } %>
+    //FOR <% for prop in &component_props { %>
+    //ITEM pub <%= prop.ident %>: <%= prop.ty %>, <% } %>
 }
 
+//IF <% if root { %>
+/// Build the props of the component, unset props get their default value.
+/// It's also used by the parent component to update only some of them.
+#[derive(Default)]
+pub struct PropsBuilder {
+    //FOR <% for prop in &component_props { %>
+    //ITEM <%= prop.ident %>: Option<<%= prop.ty %>>, <% } %>
+}
+
+impl Props {
+    pub fn builder() -> PropsBuilder {
+        PropsBuilder::default()
+    }
+}
+
+impl PropsBuilder {
+    //FOR <% for prop in &component_props { %>
+    //ITEM pub fn <%= prop.ident %>(mut self, value: <%= prop.ty %>) -> Self {
+    //ITEM self.<%= prop.ident %> = Some(value);
+    //ITEM self
+    //ITEM } <% } %>
+    pub fn build(self) -> Props {
+        Props {
+            //FOR <% for prop in &component_props { %>
+            //IF <% if let Some(default) = &prop.default { %>
+            //ITEM <%= prop.ident %>: self.<%= prop.ident %>.unwrap_or_else(|| <%= default %>),
+            //ITEM <% } else { %>
+            //ITEM <%= prop.ident %>: self.<%= prop.ident %>.expect("Missing prop `<%= prop.ident %>`."),
+            //ITEM <% } %> <% } %>
+        }
+    }
+}
+//ITEM <% } %>
+
 pub struct DOM {
+    /// Index of the row in a loop, or the component's id.
     pub id: u32,
+    /// Id of the component owning the dom, it's marked dirty to update it.
+    component_id: u32,
+    //IF <% if !root { %>
     pub props: Props,
+    //ITEM <% } %>
     //FOR <% for (ident, type_) in &fields { %>
     //ITEM <%= ident %>: <%= type_ %>, <% } %>
     // button0: Button,
@@ -21,7 +62,17 @@ pub struct DOM {
 }
 
 impl DOM {
-    pub fn from_state(state: Rc<RefCell<State>>, id: u32, props: Props) -> Result<Self, JsValue> {
+    //IF <% if root { %>
+    pub fn from_state(state: Rc<RefCell<State>>, id: u32) -> Result<Self, JsValue> {
+        let component_id = id;
+    //ITEM <% } else { %>
+    pub fn from_state(
+        state: Rc<RefCell<State>>,
+        component_id: u32,
+        id: u32,
+        props: Props,
+    ) -> Result<Self, JsValue> {
+    //ITEM <% } %>
         let document = document!();
 
         //FOR <% for statement in &init { %>
@@ -29,21 +80,46 @@ impl DOM {
 
         Ok(Self {
             id,
+            component_id,
             //FOR <% for (ident, _) in &fields { %>
             //ITEM <%= ident %>, <% } %>
             // input,
             // button0,
+            //IF <% if !root { %>
             props,
+            //ITEM <% } %>
             state,
             mounted: false,
         })
     }
-    //IF <% if props.len() == 0 { %>
+    //IF <% if root { %>
     pub fn new(runtime: Rc<RefCell<Runtime>>) -> Result<Self, JsValue> {
+        DOM::with_props(runtime, None, Props::builder().build())
+    }
+    /// `parent` is the id of the component using this one, contexts are found from it.
+    pub fn with_props(
+        runtime: Rc<RefCell<Runtime>>,
+        parent: Option<u32>,
+        props: Props,
+    ) -> Result<Self, JsValue> {
         let id = runtime.borrow_mut().next_key();
-        let state = State::new(runtime, id);
+        if let Some(parent) = parent {
+            runtime.borrow_mut().parents.insert(id, parent);
+        }
+        let state = State::new(runtime, id, props);
 
-        DOM::from_state(state, id, Props {})
+        DOM::from_state(state, id)
+    }
+    /// Props given again by the parent component, the component is marked for update.
+    pub fn set_props(&self, #[allow(unused_variables)] props: PropsBuilder) {
+        #[allow(unused_mut)]
+        let mut state = self.state.borrow_mut();
+        //FOR <% for prop in &component_props { %>
+        //ITEM if let Some(value) = props.<%= prop.ident %> {
+        //ITEM state.<%= prop.ident %> = value;
+        //ITEM state.updated_idents.insert("<%= prop.ident %>".to_string());
+        //ITEM } <% } %>
+        state.runtime.borrow_mut().dirty_ids.insert(self.id);
     }
     //ITEM <% } %>
 }
@@ -52,6 +128,14 @@ impl Drop for DOM {
     fn drop(&mut self) {
         //FOR <% for statement in &drop { %>
         //ITEM <%= statement %> <% } %>
+        //IF <% if root { %>
+        self.state.borrow().runtime.borrow_mut().remove_contexts(self.id);
+        let store_unsubscribes: Vec<_> =
+            self.state.borrow_mut().store_unsubscribes.drain(..).collect();
+        for unsubscribe in store_unsubscribes {
+            unsubscribe();
+        }
+        //ITEM <% } %>
         // remove_listener!(self.button0, "click", self.state, 0).unwrap_throw();
         // remove_listener!(self.input, "change", self.state, 1).unwrap_throw();
     }
@@ -77,6 +161,22 @@ impl DOMExt for DOM {
         //     .set_value_as_number(self.state.borrow().counter as f64);
         Ok(())
     }
+    fn move_before(&self, target: &web_sys::Element) -> Result<(), JsValue> {
+        #[allow(unused_variables)]
+        let parent = target.parent_node().unwrap_throw();
+        //FOR <% for statement in &move_nodes { %>
+        //ITEM <%= statement %> <% } %>
+        Ok(())
+    }
+    fn listen(
+        &self,
+        #[allow(unused_variables)] event_type: &str,
+        #[allow(unused_variables)] callback: &Closure<dyn FnMut(web_sys::Event)>,
+    ) -> Result<(), JsValue> {
+        //FOR <% for statement in &listen_nodes { %>
+        //ITEM <%= statement %> <% } %>
+        Ok(())
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
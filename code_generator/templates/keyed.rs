@@ -0,0 +1,49 @@
+/// Old position of each new key, `None` when its row must be created.
+/// An old row is reused once, a repeated key gets a new row.
+fn keyed_sources<K: Hash + Eq>(old_keys: &[Option<&K>], new_keys: &[&K]) -> Vec<Option<usize>> {
+    let mut old_positions: HashMap<&K, usize> = old_keys
+        .iter()
+        .enumerate()
+        .filter_map(|(i, key)| key.map(|key| (key, i)))
+        .collect();
+    new_keys
+        .iter()
+        .map(|key| old_positions.remove(key))
+        .collect()
+}
+
+/// New positions of the reused rows which keep their place, the others are moved.
+fn stable_rows(sources: &[Option<usize>]) -> HashSet<usize> {
+    let reused: Vec<usize> = (0..sources.len()).filter(|&i| sources[i].is_some()).collect();
+    let old_order: Vec<usize> = reused.iter().filter_map(|&i| sources[i]).collect();
+    longest_increasing_subsequence(&old_order)
+        .into_iter()
+        .map(|position| reused[position])
+        .collect()
+}
+
+/// Positions (in `sequence`) of a longest strictly increasing subsequence.
+fn longest_increasing_subsequence(sequence: &[usize]) -> Vec<usize> {
+    // tails[k] : position of the smallest tail of an increasing subsequence of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; sequence.len()];
+    for (i, value) in sequence.iter().enumerate() {
+        let length = tails.partition_point(|&tail| sequence[tail] < *value);
+        if length > 0 {
+            previous[i] = Some(tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(i);
+        } else {
+            tails[length] = i;
+        }
+    }
+    let mut subsequence = Vec::with_capacity(tails.len());
+    let mut current = tails.last().copied();
+    while let Some(i) = current {
+        subsequence.push(i);
+        current = previous[i];
+    }
+    subsequence.reverse();
+    subsequence
+}
@@ -0,0 +1,10 @@
+#[allow(non_snake_case)]
+pub mod
+//ITEM <%= name %>
+{
+    use super::*;
+
+    //ITEM <%= dom._state.borrow().clone().render_once().unwrap() %>
+
+    //ITEM <%= dom.render_once().unwrap() %>
+}
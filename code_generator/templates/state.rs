@@ -3,11 +3,20 @@ pub struct State {
     //ITEM <%= field %> <% } %>
     callbacks: Vec<Closure<dyn FnMut(web_sys::Event) + 'static>>,
     updated_idents: HashSet<String>,
+    runtime: Rc<RefCell<Runtime>>,
+    store_unsubscribes: Vec<Box<dyn FnOnce()>>,
 }
 
 impl State {
-    fn new(runtime: Rc<RefCell<Runtime>>, id: u32) -> Rc<RefCell<Self>> {
+    fn new(
+        runtime: Rc<RefCell<Runtime>>,
+        id: u32,
+        #[allow(unused_variables)] props: Props,
+    ) -> Rc<RefCell<Self>> {
+        // `set_context` and `get_context` are called for this component
+        let current_component = CurrentComponent::enter(runtime.clone(), id);
         //ITEM <%= init_block %>
+        drop(current_component);
         let state = Rc::new(RefCell::new(State {
             //FOR <% for field in &fields_value { %>
             //ITEM <%= field %> <% } %>
@@ -15,8 +24,25 @@ impl State {
                //ITEM <%= callbacks.len() %>
             ),
             updated_idents: HashSet::new(),
+            runtime: runtime.clone(),
+            store_unsubscribes: Vec::new(),
         }));
 
+        // A store change marks the component dirty, the markup reading it is then updated
+        //FOR <% for store in &stores { %>
+        {
+            //ITEM let store = state.borrow().<%= store %>.clone();
+            let runtime = runtime.clone();
+            let subscription = store.subscribe(move |_| {
+                runtime.borrow_mut().dirty_ids.insert(id);
+            });
+            state
+                .borrow_mut()
+                .store_unsubscribes
+                .push(Box::new(move || store.unsubscribe(subscription)));
+        }
+        //ITEM <% } %>
+
         //FOR <% for (_, callback) in &callbacks { %>
         //ITEM <%= callback %> <% } %>
 